@@ -0,0 +1,89 @@
+use std::io::{Cursor, Write};
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use epubs::strong_xml::XmlRead;
+use epubs::{Content, Epub, PackageInfo};
+
+/// Builds a package document with a manifest and spine of the given size, like a typical novel.
+fn package(items: usize) -> String {
+    let mut opf = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+         <package xmlns=\"http://www.idpf.org/2007/opf\" version=\"2.0\" unique-identifier=\"BookId\">\
+         <metadata xmlns:dc=\"http://purl.org/dc/elements/1.1/\" xmlns:opf=\"http://www.idpf.org/2007/opf\">\
+         <dc:title>The Voyage Out</dc:title>\
+         <dc:language>en</dc:language>\
+         <dc:identifier id=\"BookId\">urn:uuid:8f2a5c1e-8b8e-4a57-9a50-2f0d3c2f7b11</dc:identifier>\
+         </metadata><manifest>",
+    );
+    for idx in 0..items {
+        opf += &format!(
+            "<item id=\"chapter-{0}\" href=\"text/chapter-{0}.xhtml\" media-type=\"application/xhtml+xml\"/>",
+            idx
+        );
+    }
+    opf += "</manifest><spine toc=\"ncx\">";
+    for idx in 0..items {
+        opf += &format!("<itemref idref=\"chapter-{}\"/>", idx);
+    }
+    opf += "</spine><guide/></package>";
+    opf
+}
+
+fn archive(opf: &str) -> Vec<u8> {
+    let mut zip = zip::ZipWriter::new(Cursor::new(vec![]));
+    let options = zip::write::FileOptions::default();
+    zip.start_file("mimetype", options).unwrap();
+    zip.write_all(b"application/epub+zip").unwrap();
+    zip.start_file("META-INF/container.xml", options).unwrap();
+    zip.write_all(
+        b"<container version=\"1.0\" xmlns=\"urn:oasis:names:tc:opendocument:xmlns:container\">\
+          <rootfiles><rootfile full-path=\"OEBPS/content.opf\" media-type=\"application/oebps-package+xml\"/>\
+          </rootfiles></container>",
+    )
+    .unwrap();
+    zip.start_file("OEBPS/content.opf", options).unwrap();
+    zip.write_all(opf.as_bytes()).unwrap();
+    zip.finish().unwrap().into_inner()
+}
+
+fn parse(c: &mut Criterion) {
+    let opf = package(300);
+    let mut group = c.benchmark_group("parse metadata");
+    group.bench_function("strong-xml", |b| {
+        b.iter(|| Content::from_str(black_box(&opf)).unwrap().metadata.title.len())
+    });
+    group.bench_function("fast path", |b| {
+        b.iter(|| {
+            PackageInfo::parse(black_box(&opf))
+                .unwrap()
+                .title
+                .map(|title| title.len())
+        })
+    });
+    group.finish();
+}
+
+fn open_and_read(c: &mut Criterion) {
+    let bytes = archive(&package(300));
+    let mut group = c.benchmark_group("open and read metadata");
+    group.bench_function("strong-xml", |b| {
+        b.iter(|| {
+            let mut book = Epub::new(Cursor::new(black_box(&bytes))).unwrap();
+            let resource = book.package().unwrap();
+            let title = resource.content().unwrap().metadata.title.len();
+            title
+        })
+    });
+    group.bench_function("fast path", |b| {
+        b.iter(|| {
+            let mut book = Epub::new(Cursor::new(black_box(&bytes))).unwrap();
+            let resource = book.package().unwrap();
+            let title = resource.info().unwrap().title.map(|title| title.len());
+            title
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, parse, open_and_read);
+criterion_main!(benches);
@@ -0,0 +1,288 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+
+use crate::chapter::{self, Chapter};
+use crate::storage::Storage;
+use crate::{media_type, path, text, Content, Epub, Href};
+
+/// Number of characters of context stored on each side of an anchor.
+const CONTEXT: usize = 32;
+/// Shortest piece of text searched for when the anchored text can't be found verbatim.
+const MIN_PATTERN: usize = 8;
+/// Matches scoring lower than this are not considered to be the anchored text.
+const MIN_CONFIDENCE: f32 = 0.5;
+
+/// Position in the text of a chapter, like a bookmark or an annotation, which can be found again
+/// in later revisions of the book with [`Epub::reanchor`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Anchor {
+    /// Manifest id of the chapter.
+    pub chapter: String,
+    pub href: String,
+    /// [`Chapter::text_hash`] of the chapter when the anchor was created.
+    pub text_hash: u64,
+    /// Offset of the anchored text in characters of the text returned by [`Epub::chapter_text`].
+    pub offset: usize,
+    /// Anchored text, empty for anchors marking a position.
+    pub text: String,
+    /// Text preceding the anchored text.
+    pub before: String,
+    /// Text following the anchored text.
+    pub after: String,
+}
+
+/// Location an anchor was found at.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Resolved {
+    /// Manifest id of the chapter.
+    pub chapter: String,
+    pub href: String,
+    /// Offset of the anchored text in characters of the chapter text.
+    pub offset: usize,
+    /// Length of the anchored text in characters.
+    pub len: usize,
+    /// How closely the text around the location matches the anchor, 1 for an unchanged chapter.
+    pub confidence: f32,
+}
+
+impl<S: Storage> Epub<S> {
+    /// Returns the text of a document the way chapter hashes and anchor offsets see it,
+    /// the text of its body with runs of whitespace collapsed.
+    pub fn chapter_text(&mut self, href: Href<'_, media_type::XHtml>) -> Result<String> {
+        let resource = self.read(href)?;
+        Ok(text::body_text(&resource.doc()?))
+    }
+
+    /// Creates an anchor for `len` characters of the chapter text starting at `offset`.
+    pub fn anchor(&mut self, chapter: &Chapter, offset: usize, len: usize) -> Result<Anchor> {
+        let href = Href::try_new(chapter.href.as_str())?;
+        let text: Vec<char> = self.chapter_text(href)?.chars().collect();
+        let end = offset
+            .checked_add(len)
+            .filter(|end| *end <= text.len())
+            .ok_or_else(|| anyhow!("anchor is outside of the chapter text"))?;
+        Ok(Anchor {
+            chapter: chapter.id.clone(),
+            href: chapter.href.clone(),
+            text_hash: chapter.text_hash,
+            offset,
+            text: text[offset..end].iter().collect(),
+            before: text[offset.saturating_sub(CONTEXT)..offset].iter().collect(),
+            after: text[end..end.saturating_add(CONTEXT).min(text.len())].iter().collect(),
+        })
+    }
+
+    /// Finds an anchor in the current revision of the book. The chapter it was created in is tried
+    /// first, by manifest id and then by href, and if the text is not found there the rest of the spine
+    /// is searched. Text which was edited is matched approximately using the stored context.
+    /// Returns `None` if no location matches well enough.
+    pub fn reanchor(&mut self, content: &Content, anchor: &Anchor) -> Result<Option<Resolved>> {
+        let deadline = self.parse_deadline();
+        let href = path::normalize(&anchor.href);
+        let mut items: Vec<_> = content.spine_items().collect();
+        items.sort_by_key(|item| {
+            if item.id == anchor.chapter {
+                0
+            } else if path::normalize(&item.href) == href {
+                1
+            } else {
+                2
+            }
+        });
+
+        let quote: Vec<char> = anchor.text.chars().collect();
+        let before: Vec<char> = anchor.before.chars().collect();
+        let after: Vec<char> = anchor.after.chars().collect();
+        let mut best: Option<Resolved> = None;
+        for document in self.spine_documents(items, deadline) {
+            let document = document?;
+            let text = match document.doc() {
+                Some(doc) => text::body_text(&doc),
+                None => continue,
+            };
+            let resolved = |offset, len, confidence| Resolved {
+                chapter: document.item.id.clone().into_owned(),
+                href: document.href.as_ref().to_owned(),
+                offset,
+                len,
+                confidence,
+            };
+            if chapter::text_hash(&text) == anchor.text_hash {
+                return Ok(Some(resolved(anchor.offset, quote.len(), 1.0)));
+            }
+
+            let text: Vec<char> = text.chars().collect();
+            if let Some((offset, confidence)) = locate(&text, &quote, &before, &after, anchor.offset) {
+                if best.as_ref().is_none_or(|best| confidence > best.confidence) {
+                    best = Some(resolved(offset, quote.len().min(text.len() - offset), confidence));
+                }
+                if confidence >= 1.0 {
+                    break;
+                }
+            }
+        }
+        Ok(best.filter(|best| best.confidence >= MIN_CONFIDENCE))
+    }
+}
+
+/// Finds the offset in `text` which best matches the anchored text and its context.
+fn locate(text: &[char], quote: &[char], before: &[char], after: &[char], hint: usize) -> Option<(usize, f32)> {
+    let mut candidates = find_all(text, quote);
+    if candidates.is_empty() {
+        if quote.len() >= 2 * MIN_PATTERN {
+            let half = quote.len() / 2;
+            candidates.extend(find_all(text, &quote[..half]));
+            candidates.extend(
+                find_all(text, &quote[half..])
+                    .into_iter()
+                    .filter_map(|pos| pos.checked_sub(half)),
+            );
+        }
+        let preceding = &before[before.len().saturating_sub(CONTEXT / 2)..];
+        if preceding.len() >= MIN_PATTERN {
+            candidates.extend(find_all(text, preceding).into_iter().map(|pos| pos + preceding.len()));
+        }
+        let following = &after[..after.len().min(CONTEXT / 2)];
+        if following.len() >= MIN_PATTERN {
+            candidates.extend(
+                find_all(text, following)
+                    .into_iter()
+                    .filter_map(|pos| pos.checked_sub(quote.len())),
+            );
+        }
+    }
+
+    candidates
+        .into_iter()
+        .filter(|pos| *pos <= text.len())
+        .map(|pos| {
+            let window = &text[pos..(pos + quote.len()).min(text.len())];
+            let preceding = &text[pos.saturating_sub(before.len())..pos];
+            let following_start = (pos + quote.len()).min(text.len());
+            let following = &text[following_start..(following_start + after.len()).min(text.len())];
+            let context = (similarity(preceding, before) + similarity(following, after)) / 2.0;
+            let confidence = if quote.is_empty() {
+                context
+            } else {
+                0.6 * similarity(window, quote) + 0.4 * context
+            };
+            (pos, confidence)
+        })
+        .max_by(|(a_pos, a), (b_pos, b)| {
+            a.total_cmp(b)
+                .then_with(|| b_pos.abs_diff(hint).cmp(&a_pos.abs_diff(hint)))
+        })
+}
+
+fn find_all(text: &[char], pattern: &[char]) -> Vec<usize> {
+    if pattern.is_empty() {
+        return vec![];
+    }
+    text.windows(pattern.len())
+        .enumerate()
+        .filter(|(_, window)| *window == pattern)
+        .map(|(pos, _)| pos)
+        .collect()
+}
+
+/// Dice coefficient of the character bigrams of both texts, 1 for identical texts.
+fn similarity(a: &[char], b: &[char]) -> f32 {
+    if a == b {
+        return 1.0;
+    }
+    if a.len() < 2 || b.len() < 2 {
+        return 0.0;
+    }
+    let mut bigrams: HashMap<(char, char), usize> = HashMap::new();
+    for pair in a.windows(2) {
+        *bigrams.entry((pair[0], pair[1])).or_default() += 1;
+    }
+    let mut common = 0;
+    for pair in b.windows(2) {
+        if let Some(count) = bigrams.get_mut(&(pair[0], pair[1])) {
+            if *count > 0 {
+                *count -= 1;
+                common += 1;
+            }
+        }
+    }
+    2.0 * common as f32 / (a.len() + b.len() - 2) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{book, package, xhtml};
+
+    fn chars(text: &str) -> Vec<char> {
+        text.chars().collect()
+    }
+
+    #[test]
+    fn similarity_of_texts() {
+        assert_eq!(similarity(&chars("night"), &chars("night")), 1.0);
+        assert_eq!(similarity(&chars("abc"), &chars("xyz")), 0.0);
+        assert_eq!(similarity(&chars("a"), &chars("b")), 0.0);
+        let close = similarity(&chars("the quick brown fox"), &chars("the quick brown cat"));
+        let far = similarity(&chars("the quick brown fox"), &chars("a slow grey wolf"));
+        assert!(close > 0.7 && close < 1.0);
+        assert!(far < close);
+    }
+
+    #[test]
+    fn picks_the_occurrence_with_matching_context() {
+        let text = chars("He said yes. Later she said yes. Then they left.");
+        let (offset, confidence) =
+            locate(&text, &chars("said yes"), &chars("Later she "), &chars(". Then"), 0).unwrap();
+        assert_eq!(offset, 23);
+        assert_eq!(confidence, 1.0);
+    }
+
+    #[test]
+    fn finds_edited_text() {
+        let text = chars("It was a dark and stormy nihgt, the rain fell in torrents.");
+        let quote = chars("a dark and stormy night, the rain");
+        let (offset, confidence) = locate(&text, &quote, &chars("It was "), &chars(" fell in"), 7).unwrap();
+        assert_eq!(offset, 7);
+        assert!(confidence > MIN_CONFIDENCE && confidence < 1.0);
+
+        let text = chars("Chapter one. A new sentence was added. Call me Ishmael.");
+        let (offset, _) = locate(&text, &[], &chars("was added. "), &chars("Call me Ishmael"), 0).unwrap();
+        assert_eq!(offset, 39);
+        assert_eq!(locate(&text, &chars("nowhere to be found"), &[], &[], 0), None);
+    }
+
+    #[test]
+    fn reanchors_in_a_revised_book() {
+        let opf = package(
+            r#"<item id="one" href="one.xhtml" media-type="application/xhtml+xml"/>
+               <item id="two" href="two.xhtml" media-type="application/xhtml+xml"/>"#,
+            &["one", "two"],
+        );
+        let one = xhtml("<p>Call me Ishmael. Some years ago, never mind how long precisely.</p>");
+        let two = xhtml("<style>p { color: red }</style><p>Whenever I find myself growing grim.</p>");
+        let mut original = book(&[("content.opf", &opf), ("one.xhtml", &one), ("two.xhtml", &two)]);
+        let resource = original.package().unwrap();
+        let content = resource.content().unwrap();
+        let chapters = original.chapters(&content).unwrap();
+        assert_eq!(
+            original
+                .chapter_text(Href::try_new(chapters[1].href.as_str()).unwrap())
+                .unwrap(),
+            "Whenever I find myself growing grim."
+        );
+        let anchor = original.anchor(&chapters[1], 11, 15).unwrap();
+        assert_eq!(anchor.text, "find myself gro");
+        assert!(original.anchor(&chapters[1], 1, usize::MAX).is_err());
+
+        let moved = xhtml("<p>Preface.</p><p>Whenever I find myself growing grim about the mouth.</p>");
+        let mut revised = book(&[("content.opf", &opf), ("one.xhtml", &moved), ("two.xhtml", &one)]);
+        let resource = revised.package().unwrap();
+        let content = resource.content().unwrap();
+        let resolved = revised.reanchor(&content, &anchor).unwrap().unwrap();
+        assert_eq!(resolved.chapter, "one");
+        assert_eq!(resolved.offset, 19);
+        assert_eq!(resolved.len, 15);
+    }
+}
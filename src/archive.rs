@@ -0,0 +1,358 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Cursor, Seek, SeekFrom, Write};
+use std::time::{Duration, SystemTime};
+
+use anyhow::{anyhow, bail, Result};
+use zip::write::FileOptions;
+use zip::CompressionMethod;
+
+use crate::storage::Storage;
+use crate::timeout::Deadline;
+use crate::{ContainerHref, Epub};
+
+/// Information kept by a zip archive outside of its files, like the archive comment
+/// and the timestamps and comments of the entries.
+///
+/// It is read with [`Epub::archive_metadata`] and written by [`Epub::repack`], [`Epub::extract_section_with`]
+/// and, with the `feed` feature, `Feed::write_epub_with`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ArchiveMetadata {
+    /// Comment of the archive, zip doesn't define an encoding for it.
+    pub comment: Vec<u8>,
+    pub entries: BTreeMap<String, EntryMetadata>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EntryMetadata {
+    pub modified: Timestamp,
+    /// Comment of the entry, written as UTF-8.
+    pub comment: String,
+}
+
+/// Date and time as stored in a zip archive, in the time zone of whoever wrote it
+/// and with a precision of two seconds. Zip can represent years from 1980 to 2107.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Timestamp {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl Default for Timestamp {
+    /// The earliest time zip can represent, 1980-01-01 00:00:00.
+    fn default() -> Self {
+        zip::DateTime::default().into()
+    }
+}
+
+impl From<zip::DateTime> for Timestamp {
+    fn from(time: zip::DateTime) -> Self {
+        Self {
+            year: time.year(),
+            month: time.month(),
+            day: time.day(),
+            hour: time.hour(),
+            minute: time.minute(),
+            second: time.second(),
+        }
+    }
+}
+
+impl<S: Storage> Epub<S> {
+    /// Writes all files of the book into a new archive, with the archive comment of `metadata`
+    /// and the timestamps it lists for the files, keyed by their path in the container.
+    /// The `mimetype` file is written first and uncompressed as EPUB requires, the rest is compressed.
+    /// Files of malformed archives sharing a name are written once, see [`crate::storage::DuplicatePolicy`].
+    pub fn repack<W: Write + Seek>(&mut self, output: W, metadata: &ArchiveMetadata) -> Result<()> {
+        let mut names: Vec<String> = self.storage.entries()?.into_iter().map(|entry| entry.name).collect();
+        names.sort_by_key(|name| name != MIMETYPE);
+
+        let mut zip = ArchiveWriter::new(output, metadata);
+        for name in names {
+            let bytes = self.read_container_bytes(&ContainerHref::entry(name.as_str()), Deadline::NONE)?;
+            let options = if name == MIMETYPE {
+                FileOptions::default().compression_method(CompressionMethod::Stored)
+            } else {
+                FileOptions::default()
+            };
+            zip.start_file(&name, options)?;
+            zip.write_all(&bytes)?;
+        }
+        zip.finish()
+    }
+}
+
+const MIMETYPE: &str = "mimetype";
+
+/// Zip writer applying [`ArchiveMetadata`] to the archive and the files written into it.
+///
+/// The zip writer has no support for comments of individual files, when the metadata has any
+/// the archive is built in memory and the comments are added to its central directory once it's complete.
+pub(crate) struct ArchiveWriter<'m, W: Write + Seek> {
+    zip: zip::ZipWriter<Output<W>>,
+    metadata: &'m ArchiveMetadata,
+    comments: HashMap<String, &'m str>,
+}
+
+impl<'m, W: Write + Seek> ArchiveWriter<'m, W> {
+    pub(crate) fn new(output: W, metadata: &'m ArchiveMetadata) -> Self {
+        let output = if metadata.entries.values().any(|entry| !entry.comment.is_empty()) {
+            Output::Buffered(Cursor::new(vec![]), output)
+        } else {
+            Output::Direct(output)
+        };
+        let mut zip = zip::ZipWriter::new(output);
+        zip.set_raw_comment(metadata.comment.clone());
+        Self {
+            zip,
+            metadata,
+            comments: HashMap::new(),
+        }
+    }
+
+    /// Starts a new file, with the timestamp and comment the metadata lists for it.
+    pub(crate) fn start_file(&mut self, name: &str, options: FileOptions) -> Result<()> {
+        self.start_file_from(name, name, options)
+    }
+
+    /// Like [`ArchiveWriter::start_file`], for a copy of the file `source` stored under another name.
+    pub(crate) fn start_file_from(&mut self, source: &str, name: &str, options: FileOptions) -> Result<()> {
+        let options = match self.metadata.entries.get(source) {
+            Some(entry) => {
+                if !entry.comment.is_empty() {
+                    self.comments.insert(name.to_owned(), &entry.comment);
+                }
+                options.last_modified_time(entry.modified.to_zip())
+            }
+            None => options,
+        };
+        self.zip.start_file(name, options)?;
+        Ok(())
+    }
+
+    pub(crate) fn finish(mut self) -> Result<()> {
+        match self.zip.finish()? {
+            Output::Direct(_) => {}
+            Output::Buffered(archive, mut output) => {
+                let archive = add_entry_comments(archive.get_ref(), self.metadata.comment.len(), &self.comments)?;
+                output.write_all(&archive)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'m, W: Write + Seek> Write for ArchiveWriter<'m, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.zip.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.zip.flush()
+    }
+}
+
+enum Output<W> {
+    Direct(W),
+    Buffered(Cursor<Vec<u8>>, W),
+}
+
+impl<W: Write> Write for Output<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Direct(output) => output.write(buf),
+            Output::Buffered(archive, _) => archive.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Direct(output) => output.flush(),
+            Output::Buffered(archive, _) => archive.flush(),
+        }
+    }
+}
+
+impl<W: Seek> Seek for Output<W> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            Output::Direct(output) => output.seek(pos),
+            Output::Buffered(archive, _) => archive.seek(pos),
+        }
+    }
+}
+
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x02014b50;
+const CENTRAL_END_SIGNATURE: u32 = 0x06054b50;
+const CENTRAL_HEADER_LEN: usize = 46;
+const CENTRAL_END_LEN: usize = 22;
+
+/// Rewrites the central directory of a complete archive with comments of its entries, keyed by name.
+fn add_entry_comments(archive: &[u8], archive_comment_len: usize, comments: &HashMap<String, &str>) -> Result<Vec<u8>> {
+    let unexpected = || anyhow!("unexpected layout of the written archive");
+    let end = archive
+        .len()
+        .checked_sub(CENTRAL_END_LEN + archive_comment_len)
+        .ok_or_else(unexpected)?;
+    if read_u32(archive, end) != Some(CENTRAL_END_SIGNATURE) {
+        return Err(unexpected());
+    }
+    let size = read_u32(archive, end + 12).ok_or_else(unexpected)? as usize;
+    let start = read_u32(archive, end + 16).ok_or_else(unexpected)? as usize;
+    if start + size != end {
+        bail!("entry comments can't be written into a zip64 archive");
+    }
+
+    let mut result = archive[..start].to_vec();
+    let mut pos = start;
+    while pos < end {
+        if read_u32(archive, pos) != Some(CENTRAL_HEADER_SIGNATURE) {
+            return Err(unexpected());
+        }
+        let field = |offset| read_u16(archive, pos + offset).map(usize::from).ok_or_else(unexpected);
+        let (name_len, extra_len, comment_len) = (field(28)?, field(30)?, field(32)?);
+        let header_len = CENTRAL_HEADER_LEN + name_len + extra_len;
+        let mut header = archive.get(pos..pos + header_len).ok_or_else(unexpected)?.to_vec();
+        let name = String::from_utf8_lossy(&header[CENTRAL_HEADER_LEN..CENTRAL_HEADER_LEN + name_len]).into_owned();
+
+        let comment = comments
+            .get(&name)
+            .map(|comment| comment.as_bytes())
+            .unwrap_or_default();
+        let len = u16::try_from(comment.len()).map_err(|_| anyhow!("comment of {} is too long", name))?;
+        header[32..34].copy_from_slice(&len.to_le_bytes());
+        if !comment.is_ascii() {
+            // Marks the name and the comment as UTF-8, readers assume CP437 otherwise.
+            header[9] |= 1 << 3;
+        }
+        result.extend_from_slice(&header);
+        result.extend_from_slice(comment);
+        pos += header_len + comment_len;
+    }
+
+    let directory_len = u32::try_from(result.len() - start)?;
+    let mut footer = archive[end..].to_vec();
+    footer[12..16].copy_from_slice(&directory_len.to_le_bytes());
+    result.extend_from_slice(&footer);
+    Ok(result)
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(bytes.get(offset..offset + 2)?.try_into().ok()?))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(bytes.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+impl Timestamp {
+    /// Converts the timestamp to a point in time, taking it to be in UTC since zip doesn't record the time zone.
+    /// Returns `None` for dates which don't exist.
+    pub fn to_system_time(self) -> Option<SystemTime> {
+        let days_in_month = match self.month {
+            1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+            4 | 6 | 9 | 11 => 30,
+            2 if self.year.is_multiple_of(4) && (!self.year.is_multiple_of(100) || self.year.is_multiple_of(400)) => 29,
+            2 => 28,
+            _ => return None,
+        };
+        if self.day == 0 || self.day > days_in_month || self.hour > 23 || self.minute > 59 || self.second > 59 {
+            return None;
+        }
+        // Days since the epoch of a proleptic Gregorian date, with years starting in March.
+        let year = i64::from(self.year) - i64::from(self.month <= 2);
+        let era = year.div_euclid(400);
+        let year_of_era = year - era * 400;
+        let month = (i64::from(self.month) + 9) % 12;
+        let day_of_year = (153 * month + 2) / 5 + i64::from(self.day) - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = era * 146097 + day_of_era - 719468;
+        let seconds = days * 86400 + i64::from(self.hour) * 3600 + i64::from(self.minute) * 60 + i64::from(self.second);
+        Some(SystemTime::UNIX_EPOCH + Duration::from_secs(u64::try_from(seconds).ok()?))
+    }
+
+    /// Converts the timestamp for writing, falling back to the default for values zip can't represent.
+    pub(crate) fn to_zip(self) -> zip::DateTime {
+        zip::DateTime::from_date_and_time(self.year, self.month, self.day, self.hour, self.minute, self.second)
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn timestamp(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> Timestamp {
+        Timestamp {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+        }
+    }
+
+    #[test]
+    fn repack_applies_archive_metadata() {
+        let mut zip = zip::ZipWriter::new(Cursor::new(vec![]));
+        zip.set_comment("provenance: scanned 2019");
+        let options = FileOptions::default();
+        let old = zip::DateTime::from_date_and_time(2019, 5, 1, 10, 0, 0).unwrap();
+        zip.start_file("OEBPS/content.opf", options.last_modified_time(old))
+            .unwrap();
+        zip.write_all(b"<package/>").unwrap();
+        zip.start_file("mimetype", options).unwrap();
+        zip.write_all(b"application/epub+zip").unwrap();
+        let bytes = zip.finish().unwrap().into_inner();
+
+        let mut book = Epub::new(Cursor::new(bytes)).unwrap();
+        let mut metadata = book.archive_metadata().unwrap();
+        assert_eq!(metadata.comment, b"provenance: scanned 2019");
+        assert_eq!(metadata.entries["OEBPS/content.opf"].modified, old.into());
+
+        let mut repacked = Cursor::new(vec![]);
+        book.repack(&mut repacked, &metadata).unwrap();
+        let mut copy = Epub::new(Cursor::new(repacked.into_inner())).unwrap();
+        assert_eq!(copy.archive_metadata().unwrap(), metadata);
+
+        metadata.comment = b"provenance: repacked".to_vec();
+        metadata.entries.get_mut("OEBPS/content.opf").unwrap().comment = "checked by hand".to_owned();
+        metadata.entries.get_mut("mimetype").unwrap().comment = "généré".to_owned();
+        let mut repacked = Cursor::new(vec![]);
+        book.repack(&mut repacked, &metadata).unwrap();
+        let repacked = repacked.into_inner();
+        let mut copy = Epub::new(Cursor::new(repacked.clone())).unwrap();
+        assert_eq!(copy.archive_metadata().unwrap(), metadata);
+        assert_eq!(
+            copy.read_container_bytes(&ContainerHref::entry("OEBPS/content.opf"), Deadline::NONE)
+                .unwrap(),
+            b"<package/>"
+        );
+
+        let mut archive = zip::ZipArchive::new(Cursor::new(repacked)).unwrap();
+        assert_eq!(archive.comment(), b"provenance: repacked");
+        let first = archive.by_index(0).unwrap();
+        assert_eq!(first.name(), "mimetype");
+        assert_eq!(first.compression(), CompressionMethod::Stored);
+    }
+
+    #[test]
+    fn converts_to_system_time() {
+        let seconds = |timestamp: Timestamp| {
+            timestamp
+                .to_system_time()
+                .map(|time| time.duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs())
+        };
+        assert_eq!(seconds(Timestamp::default()), Some(315532800));
+        assert_eq!(seconds(timestamp(2020, 2, 29, 12, 34, 56)), Some(1582979696));
+        assert_eq!(seconds(timestamp(2000, 12, 31, 23, 59, 58)), Some(978307198));
+        assert_eq!(seconds(timestamp(2021, 2, 29, 0, 0, 0)), None);
+        assert_eq!(seconds(timestamp(2021, 13, 1, 0, 0, 0)), None);
+    }
+}
@@ -0,0 +1,822 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+
+use crate::{Content, Item, ItemRef, Meta};
+
+/// A single modification of the package document.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    /// A Dublin Core field of the metadata, like `title`, changed its value.
+    MetadataChanged {
+        field: &'static str,
+        old: String,
+        new: String,
+    },
+    ResourceAdded {
+        id: String,
+        href: String,
+        media_type: String,
+    },
+    ResourceRemoved {
+        id: String,
+        href: String,
+        media_type: String,
+    },
+    /// The manifest item pointing at this href changed its id, like when [`Content::repair_manifest`]
+    /// renames duplicates.
+    ResourceRenamed {
+        href: String,
+        old: String,
+        new: String,
+    },
+    ResourceMediaTypeChanged {
+        href: String,
+        old: String,
+        new: String,
+    },
+    ResourcePropertiesChanged {
+        href: String,
+        old: Option<String>,
+        new: Option<String>,
+    },
+    /// The reading order changed, listed as the ids referenced by the spine before and after.
+    SpineChanged {
+        old: Vec<String>,
+        new: Vec<String>,
+    },
+    /// An attribute of the package, `unique-identifier` of the package element or `toc` of the spine,
+    /// changed its value.
+    AttributeChanged {
+        attribute: &'static str,
+        old: Option<String>,
+        new: Option<String>,
+    },
+    /// A `meta` record of the metadata was added, removed or changed its content.
+    MetaChanged {
+        name: String,
+        old: Option<String>,
+        new: Option<String>,
+    },
+    GuideChanged {
+        old: Vec<GuideEntry>,
+        new: Vec<GuideEntry>,
+    },
+}
+
+/// Reference of the guide, as listed by [`Change::GuideChanged`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GuideEntry {
+    pub kind: String,
+    pub title: String,
+    pub href: String,
+}
+
+/// Record of the modifications made to a book, kept so automated fixes can be audited.
+///
+/// An [`Editor`] logs each change as it's made. Changes made elsewhere can be found
+/// by comparing snapshots of the package document taken before and after the edit.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Changelog {
+    pub changes: Vec<Change>,
+}
+
+impl Changelog {
+    /// Name of the `meta` record the changelog is stored under by [`Changelog::to_meta`].
+    pub const META_NAME: &'static str = "epubs:changelog";
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the changes turning `before` into `after`.
+    pub fn diff(before: &Content, after: &Content) -> Self {
+        let mut changelog = Self::new();
+        changelog.record(before, after);
+        changelog
+    }
+
+    /// Appends the changes turning `before` into `after`. The `meta` record holding
+    /// an embedded changelog is not compared.
+    pub fn record(&mut self, before: &Content, after: &Content) {
+        let attributes = [
+            ("unique-identifier", &before.unique_identifier, &after.unique_identifier),
+            ("toc", &before.spine.toc, &after.spine.toc),
+        ];
+        for (attribute, old, new) in attributes {
+            if old != new {
+                self.changes.push(Change::AttributeChanged {
+                    attribute,
+                    old: old.as_deref().map(str::to_owned),
+                    new: new.as_deref().map(str::to_owned),
+                });
+            }
+        }
+
+        let fields = [
+            ("title", &before.metadata.title, &after.metadata.title),
+            ("language", &before.metadata.language, &after.metadata.language),
+            ("identifier", &before.metadata.identifier, &after.metadata.identifier),
+        ];
+        for (field, old, new) in fields {
+            if old != new {
+                self.changes.push(Change::MetadataChanged {
+                    field,
+                    old: old.clone().into_owned(),
+                    new: new.clone().into_owned(),
+                });
+            }
+        }
+
+        let old_items = items_by_href(before);
+        let new_items = items_by_href(after);
+        for item in &before.manifest.items {
+            if !old_items
+                .get(item.href.as_ref())
+                .is_some_and(|first| std::ptr::eq(*first, item))
+            {
+                continue;
+            }
+            match new_items.get(item.href.as_ref()) {
+                None => self.changes.push(Change::ResourceRemoved {
+                    id: item.id.clone().into_owned(),
+                    href: item.href.clone().into_owned(),
+                    media_type: item.media_type.clone().into_owned(),
+                }),
+                Some(new) => {
+                    if new.id != item.id {
+                        self.changes.push(Change::ResourceRenamed {
+                            href: item.href.clone().into_owned(),
+                            old: item.id.clone().into_owned(),
+                            new: new.id.clone().into_owned(),
+                        });
+                    }
+                    if new.media_type != item.media_type {
+                        self.changes.push(Change::ResourceMediaTypeChanged {
+                            href: item.href.clone().into_owned(),
+                            old: item.media_type.clone().into_owned(),
+                            new: new.media_type.clone().into_owned(),
+                        });
+                    }
+                    if new.properties != item.properties {
+                        self.changes.push(Change::ResourcePropertiesChanged {
+                            href: item.href.clone().into_owned(),
+                            old: item.properties.as_deref().map(str::to_owned),
+                            new: new.properties.as_deref().map(str::to_owned),
+                        });
+                    }
+                }
+            }
+        }
+        for item in &after.manifest.items {
+            if !old_items.contains_key(item.href.as_ref()) {
+                self.changes.push(Change::ResourceAdded {
+                    id: item.id.clone().into_owned(),
+                    href: item.href.clone().into_owned(),
+                    media_type: item.media_type.clone().into_owned(),
+                });
+            }
+        }
+
+        let old_spine = spine_ids(before);
+        let new_spine = spine_ids(after);
+        if old_spine != new_spine {
+            self.changes.push(Change::SpineChanged {
+                old: old_spine,
+                new: new_spine,
+            });
+        }
+
+        let old_meta = named_meta(before);
+        let new_meta = named_meta(after);
+        for (name, old) in &old_meta {
+            let new = meta_content(&new_meta, name).flatten();
+            if *old != new {
+                self.changes.push(Change::MetaChanged {
+                    name: (*name).to_owned(),
+                    old: old.map(str::to_owned),
+                    new: new.map(str::to_owned),
+                });
+            }
+        }
+        for (name, new) in &new_meta {
+            if meta_content(&old_meta, name).is_none() {
+                self.changes.push(Change::MetaChanged {
+                    name: (*name).to_owned(),
+                    old: None,
+                    new: new.map(str::to_owned),
+                });
+            }
+        }
+
+        let old_guide = guide_entries(before);
+        let new_guide = guide_entries(after);
+        if old_guide != new_guide {
+            self.changes.push(Change::GuideChanged {
+                old: old_guide,
+                new: new_guide,
+            });
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        self.changes.iter().map(Change::to_json).collect()
+    }
+
+    /// Returns the changelog as a `meta` record holding its JSON, to be embedded into the book.
+    pub fn to_meta(&self) -> Meta<'static> {
+        Meta {
+            name: Some(Cow::Borrowed(Self::META_NAME)),
+            content: Some(Cow::Owned(self.to_json().to_string())),
+        }
+    }
+
+    /// Writes the changelog into the metadata of the package as a `meta` record. Changes recorded
+    /// by earlier sessions, if the package already holds a changelog, are kept in front of these.
+    pub fn embed(&self, content: &mut Content) {
+        let mut json = Self::embedded(content);
+        json.extend(self.changes.iter().map(Change::to_json));
+        let meta = Meta {
+            name: Some(Cow::Borrowed(Self::META_NAME)),
+            content: Some(Cow::Owned(serde_json::Value::from(json).to_string())),
+        };
+        let metas = &mut content.metadata.meta;
+        match metas
+            .iter_mut()
+            .find(|meta| meta.name.as_deref() == Some(Self::META_NAME))
+        {
+            Some(existing) => *existing = meta,
+            None => metas.push(meta),
+        }
+    }
+
+    /// Returns the JSON entries of the changelog embedded into the package, empty if there is none.
+    pub fn embedded(content: &Content) -> Vec<serde_json::Value> {
+        content
+            .metadata
+            .meta
+            .iter()
+            .find(|meta| meta.name.as_deref() == Some(Self::META_NAME))
+            .and_then(|meta| meta.content.as_deref())
+            .and_then(|json| {
+                // attribute values read from a file are left escaped by the parser
+                serde_json::from_str(json).ok().or_else(|| {
+                    let json = strong_xml::utils::xml_unescape(json).ok()?;
+                    serde_json::from_str(&json).ok()
+                })
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// Editing session over a package document which logs each change as it's made,
+/// in order and including intermediate steps undone by later ones.
+#[derive(Debug, Clone)]
+pub struct Editor<'a> {
+    content: Content<'a>,
+    changelog: Changelog,
+}
+
+impl<'a> Editor<'a> {
+    pub fn new(content: Content<'a>) -> Self {
+        Self {
+            content,
+            changelog: Changelog::new(),
+        }
+    }
+
+    pub fn content(&self) -> &Content<'a> {
+        &self.content
+    }
+
+    pub fn changelog(&self) -> &Changelog {
+        &self.changelog
+    }
+
+    /// Ends the session, returning the edited package and the changes made to it.
+    pub fn finish(self) -> (Content<'a>, Changelog) {
+        (self.content, self.changelog)
+    }
+
+    pub fn set_title(&mut self, title: impl Into<Cow<'a, str>>) {
+        let title = title.into();
+        Self::set_field(&mut self.changelog, "title", &mut self.content.metadata.title, title);
+    }
+
+    pub fn set_language(&mut self, language: impl Into<Cow<'a, str>>) {
+        let language = language.into();
+        Self::set_field(
+            &mut self.changelog,
+            "language",
+            &mut self.content.metadata.language,
+            language,
+        );
+    }
+
+    pub fn set_identifier(&mut self, identifier: impl Into<Cow<'a, str>>) {
+        let identifier = identifier.into();
+        Self::set_field(
+            &mut self.changelog,
+            "identifier",
+            &mut self.content.metadata.identifier,
+            identifier,
+        );
+    }
+
+    /// Sets the content of the `meta` record with the given name, adding the record if there is none.
+    /// `None` removes the records with that name.
+    pub fn set_meta(&mut self, name: &str, content: Option<Cow<'a, str>>) {
+        let metas = &mut self.content.metadata.meta;
+        let old = metas
+            .iter()
+            .find(|meta| meta.name.as_deref() == Some(name))
+            .and_then(|meta| meta.content.as_deref())
+            .map(str::to_owned);
+        match &content {
+            Some(content) => match metas.iter_mut().find(|meta| meta.name.as_deref() == Some(name)) {
+                Some(meta) => meta.content = Some(content.clone()),
+                None => metas.push(Meta {
+                    name: Some(Cow::Owned(name.to_owned())),
+                    content: Some(content.clone()),
+                }),
+            },
+            None => metas.retain(|meta| meta.name.as_deref() != Some(name)),
+        }
+        let new = content.map(Cow::into_owned);
+        if old != new {
+            self.changelog.changes.push(Change::MetaChanged {
+                name: name.to_owned(),
+                old,
+                new,
+            });
+        }
+    }
+
+    pub fn add_item(
+        &mut self,
+        id: impl Into<Cow<'a, str>>,
+        href: impl Into<Cow<'a, str>>,
+        media_type: impl Into<Cow<'a, str>>,
+    ) -> Result<()> {
+        let item = Item {
+            id: id.into(),
+            media_type: media_type.into(),
+            href: href.into(),
+            properties: None,
+        };
+        let items = &self.content.manifest.items;
+        if items.iter().any(|existing| existing.id == item.id) {
+            return Err(anyhow!("manifest already has an item with id '{}'", item.id));
+        }
+        if items.iter().any(|existing| existing.href == item.href) {
+            return Err(anyhow!("manifest already has an item with href '{}'", item.href));
+        }
+        self.changelog.changes.push(Change::ResourceAdded {
+            id: item.id.clone().into_owned(),
+            href: item.href.clone().into_owned(),
+            media_type: item.media_type.clone().into_owned(),
+        });
+        self.content.manifest.items.push(item);
+        Ok(())
+    }
+
+    /// Removes an item from the manifest. References to it are first dropped from the spine,
+    /// and the `toc` attribute of the spine is cleared if it pointed at the item, each logged as a change.
+    pub fn remove_item(&mut self, id: &str) -> Result<()> {
+        let idx = self.item_position(id)?;
+        if self.content.spine.refs.iter().any(|item_ref| item_ref.id_ref == id) {
+            self.edit_spine(|refs| refs.retain(|item_ref| item_ref.id_ref != id));
+        }
+        if self.content.spine.toc.as_deref() == Some(id) {
+            self.set_toc(None);
+        }
+        let item = self.content.manifest.items.remove(idx);
+        self.changelog.changes.push(Change::ResourceRemoved {
+            id: item.id.into_owned(),
+            href: item.href.into_owned(),
+            media_type: item.media_type.into_owned(),
+        });
+        Ok(())
+    }
+
+    /// Changes the id of an item, updating the spine references and the `toc` attribute pointing at it.
+    /// Only the rename is logged, the updated references follow from it.
+    pub fn rename_item(&mut self, id: &str, new_id: impl Into<Cow<'a, str>>) -> Result<()> {
+        let new_id = new_id.into();
+        let idx = self.item_position(id)?;
+        if self.content.manifest.items.iter().any(|item| item.id == new_id) {
+            return Err(anyhow!("manifest already has an item with id '{}'", new_id));
+        }
+        for item_ref in self
+            .content
+            .spine
+            .refs
+            .iter_mut()
+            .filter(|item_ref| item_ref.id_ref == id)
+        {
+            item_ref.id_ref = new_id.clone();
+        }
+        if self.content.spine.toc.as_deref() == Some(id) {
+            self.content.spine.toc = Some(new_id.clone());
+        }
+        let item = &mut self.content.manifest.items[idx];
+        self.changelog.changes.push(Change::ResourceRenamed {
+            href: item.href.clone().into_owned(),
+            old: id.to_owned(),
+            new: new_id.clone().into_owned(),
+        });
+        item.id = new_id;
+        Ok(())
+    }
+
+    pub fn set_media_type(&mut self, id: &str, media_type: impl Into<Cow<'a, str>>) -> Result<()> {
+        let media_type = media_type.into();
+        let idx = self.item_position(id)?;
+        let item = &mut self.content.manifest.items[idx];
+        if item.media_type != media_type {
+            self.changelog.changes.push(Change::ResourceMediaTypeChanged {
+                href: item.href.clone().into_owned(),
+                old: item.media_type.clone().into_owned(),
+                new: media_type.clone().into_owned(),
+            });
+            item.media_type = media_type;
+        }
+        Ok(())
+    }
+
+    pub fn set_properties(&mut self, id: &str, properties: Option<Cow<'a, str>>) -> Result<()> {
+        let idx = self.item_position(id)?;
+        let item = &mut self.content.manifest.items[idx];
+        if item.properties != properties {
+            self.changelog.changes.push(Change::ResourcePropertiesChanged {
+                href: item.href.clone().into_owned(),
+                old: item.properties.as_deref().map(str::to_owned),
+                new: properties.as_deref().map(str::to_owned),
+            });
+            item.properties = properties;
+        }
+        Ok(())
+    }
+
+    /// Inserts a reference to the item with the given id into the spine at `index`.
+    pub fn insert_spine(&mut self, index: usize, id: impl Into<Cow<'a, str>>) -> Result<()> {
+        let id = id.into();
+        self.item_position(&id)?;
+        if index > self.content.spine.refs.len() {
+            return Err(anyhow!("spine position {} is out of range", index));
+        }
+        self.edit_spine(|refs| refs.insert(index, ItemRef { id_ref: id }));
+        Ok(())
+    }
+
+    pub fn remove_spine(&mut self, index: usize) -> Result<()> {
+        if index >= self.content.spine.refs.len() {
+            return Err(anyhow!("spine position {} is out of range", index));
+        }
+        self.edit_spine(|refs| {
+            refs.remove(index);
+        });
+        Ok(())
+    }
+
+    /// Moves the spine reference at `from` so it ends up at `to`.
+    pub fn move_spine(&mut self, from: usize, to: usize) -> Result<()> {
+        let len = self.content.spine.refs.len();
+        if from >= len || to >= len {
+            return Err(anyhow!("spine position {} is out of range", from.max(to)));
+        }
+        if from != to {
+            self.edit_spine(|refs| {
+                let item_ref = refs.remove(from);
+                refs.insert(to, item_ref);
+            });
+        }
+        Ok(())
+    }
+
+    /// Sets the id of the NCX item referenced by the `toc` attribute of the spine.
+    pub fn set_toc(&mut self, id: Option<Cow<'a, str>>) {
+        let toc = &mut self.content.spine.toc;
+        if *toc != id {
+            self.changelog.changes.push(Change::AttributeChanged {
+                attribute: "toc",
+                old: toc.as_deref().map(str::to_owned),
+                new: id.as_deref().map(str::to_owned),
+            });
+            *toc = id;
+        }
+    }
+
+    /// Applies an edit made by code which doesn't log its changes, like [`Content::repair_manifest`],
+    /// recording them by comparing the package before and after.
+    pub fn apply<T>(&mut self, edit: impl FnOnce(&mut Content<'a>) -> T) -> T {
+        let before = self.content.clone();
+        let result = edit(&mut self.content);
+        self.changelog.record(&before, &self.content);
+        result
+    }
+
+    fn set_field(changelog: &mut Changelog, field: &'static str, value: &mut Cow<'a, str>, new: Cow<'a, str>) {
+        if *value != new {
+            changelog.changes.push(Change::MetadataChanged {
+                field,
+                old: value.clone().into_owned(),
+                new: new.clone().into_owned(),
+            });
+            *value = new;
+        }
+    }
+
+    fn edit_spine(&mut self, edit: impl FnOnce(&mut Vec<ItemRef<'a>>)) {
+        let old = spine_ids(&self.content);
+        edit(&mut self.content.spine.refs);
+        self.changelog.changes.push(Change::SpineChanged {
+            old,
+            new: spine_ids(&self.content),
+        });
+    }
+
+    fn item_position(&self, id: &str) -> Result<usize> {
+        self.content
+            .manifest
+            .items
+            .iter()
+            .position(|item| item.id == id)
+            .ok_or_else(|| anyhow!("manifest has no item with id '{}'", id))
+    }
+}
+
+impl Change {
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            Change::MetadataChanged { field, old, new } => serde_json::json!({
+                "change": "metadata",
+                "field": field,
+                "old": old,
+                "new": new,
+            }),
+            Change::ResourceAdded { id, href, media_type } => serde_json::json!({
+                "change": "resourceAdded",
+                "id": id,
+                "href": href,
+                "mediaType": media_type,
+            }),
+            Change::ResourceRemoved { id, href, media_type } => serde_json::json!({
+                "change": "resourceRemoved",
+                "id": id,
+                "href": href,
+                "mediaType": media_type,
+            }),
+            Change::ResourceRenamed { href, old, new } => serde_json::json!({
+                "change": "resourceRenamed",
+                "href": href,
+                "old": old,
+                "new": new,
+            }),
+            Change::ResourceMediaTypeChanged { href, old, new } => serde_json::json!({
+                "change": "resourceMediaType",
+                "href": href,
+                "old": old,
+                "new": new,
+            }),
+            Change::ResourcePropertiesChanged { href, old, new } => serde_json::json!({
+                "change": "resourceProperties",
+                "href": href,
+                "old": old,
+                "new": new,
+            }),
+            Change::SpineChanged { old, new } => serde_json::json!({
+                "change": "spine",
+                "old": old,
+                "new": new,
+            }),
+            Change::AttributeChanged { attribute, old, new } => serde_json::json!({
+                "change": "attribute",
+                "attribute": attribute,
+                "old": old,
+                "new": new,
+            }),
+            Change::MetaChanged { name, old, new } => serde_json::json!({
+                "change": "meta",
+                "name": name,
+                "old": old,
+                "new": new,
+            }),
+            Change::GuideChanged { old, new } => serde_json::json!({
+                "change": "guide",
+                "old": old.iter().map(GuideEntry::to_json).collect::<Vec<_>>(),
+                "new": new.iter().map(GuideEntry::to_json).collect::<Vec<_>>(),
+            }),
+        }
+    }
+}
+
+impl GuideEntry {
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "type": self.kind,
+            "title": self.title,
+            "href": self.href,
+        })
+    }
+}
+
+/// Indexes the manifest by href, keeping the first item for hrefs listed more than once.
+fn items_by_href<'c>(content: &'c Content) -> HashMap<&'c str, &'c Item<'c>> {
+    let mut items = HashMap::new();
+    for item in &content.manifest.items {
+        items.entry(item.href.as_ref()).or_insert(item);
+    }
+    items
+}
+
+fn spine_ids(content: &Content) -> Vec<String> {
+    content
+        .spine
+        .refs
+        .iter()
+        .map(|item_ref| item_ref.id_ref.clone().into_owned())
+        .collect()
+}
+
+/// Lists the content of named `meta` records in document order, keeping the first record for names
+/// used more than once and leaving out the embedded changelog.
+fn named_meta<'c>(content: &'c Content) -> Vec<(&'c str, Option<&'c str>)> {
+    let mut metas: Vec<(&str, Option<&str>)> = vec![];
+    for meta in &content.metadata.meta {
+        let name = match meta.name.as_deref() {
+            Some(name) if name != Changelog::META_NAME => name,
+            _ => continue,
+        };
+        if !metas.iter().any(|(existing, _)| *existing == name) {
+            metas.push((name, meta.content.as_deref()));
+        }
+    }
+    metas
+}
+
+fn meta_content<'c>(metas: &[(&str, Option<&'c str>)], name: &str) -> Option<Option<&'c str>> {
+    metas
+        .iter()
+        .find(|(candidate, _)| *candidate == name)
+        .map(|(_, content)| *content)
+}
+
+fn guide_entries(content: &Content) -> Vec<GuideEntry> {
+    content
+        .guide
+        .references
+        .iter()
+        .map(|reference| GuideEntry {
+            kind: reference.kind.clone().into_owned(),
+            title: reference.title.clone().into_owned(),
+            href: reference.href.clone().into_owned(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use strong_xml::{XmlRead, XmlWrite};
+
+    use super::*;
+    use crate::testing::package;
+
+    const ITEMS: &str = r#"<item id="ncx" href="toc.ncx" media-type="application/x-dtbncx+xml"/>
+        <item id="one" href="one.xhtml" media-type="application/xhtml+xml"/>
+        <item id="two" href="two.xhtml" media-type="application/xhtml+xml"/>"#;
+
+    fn spine(old: &[&str], new: &[&str]) -> Change {
+        Change::SpineChanged {
+            old: old.iter().map(|id| id.to_string()).collect(),
+            new: new.iter().map(|id| id.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn editor_logs_each_step() {
+        let opf = package(ITEMS, &["one", "two"]);
+        let mut editor = Editor::new(Content::from_str(&opf).unwrap());
+        editor.set_title("Draft");
+        editor.set_title("Final");
+        editor
+            .add_item("three", "three.xhtml", "application/xhtml+xml")
+            .unwrap();
+        editor.insert_spine(2, "three").unwrap();
+        editor.move_spine(2, 0).unwrap();
+        editor.rename_item("one", "first").unwrap();
+        editor.remove_item("two").unwrap();
+        assert!(editor.remove_item("two").is_err());
+
+        let (content, changelog) = editor.finish();
+        assert_eq!(
+            changelog.changes,
+            vec![
+                Change::MetadataChanged {
+                    field: "title",
+                    old: "Test".to_owned(),
+                    new: "Draft".to_owned(),
+                },
+                Change::MetadataChanged {
+                    field: "title",
+                    old: "Draft".to_owned(),
+                    new: "Final".to_owned(),
+                },
+                Change::ResourceAdded {
+                    id: "three".to_owned(),
+                    href: "three.xhtml".to_owned(),
+                    media_type: "application/xhtml+xml".to_owned(),
+                },
+                spine(&["one", "two"], &["one", "two", "three"]),
+                spine(&["one", "two", "three"], &["three", "one", "two"]),
+                Change::ResourceRenamed {
+                    href: "one.xhtml".to_owned(),
+                    old: "one".to_owned(),
+                    new: "first".to_owned(),
+                },
+                spine(&["three", "first", "two"], &["three", "first"]),
+                Change::ResourceRemoved {
+                    id: "two".to_owned(),
+                    href: "two.xhtml".to_owned(),
+                    media_type: "application/xhtml+xml".to_owned(),
+                },
+            ]
+        );
+        assert_eq!(spine_ids(&content), ["three", "first"]);
+    }
+
+    #[test]
+    fn diff_covers_the_whole_package() {
+        let before = package(ITEMS, &["one", "two"]);
+        let after = before
+            .replace(r#"unique-identifier="id""#, r#"unique-identifier="uid""#)
+            .replace(
+                r#"href="two.xhtml" media-type="application/xhtml+xml""#,
+                r#"href="two.xhtml" media-type="application/xhtml+xml" properties="svg""#,
+            )
+            .replace("</metadata>", r#"<meta name="cover" content="one"/></metadata>"#)
+            .replace(
+                "<guide/>",
+                r#"<guide><reference type="text" title="Start" href="one.xhtml"/></guide>"#,
+            );
+        let changelog = Changelog::diff(
+            &Content::from_str(&before).unwrap(),
+            &Content::from_str(&after).unwrap(),
+        );
+        assert_eq!(
+            changelog.changes,
+            vec![
+                Change::AttributeChanged {
+                    attribute: "unique-identifier",
+                    old: Some("id".to_owned()),
+                    new: Some("uid".to_owned()),
+                },
+                Change::ResourcePropertiesChanged {
+                    href: "two.xhtml".to_owned(),
+                    old: None,
+                    new: Some("svg".to_owned()),
+                },
+                Change::MetaChanged {
+                    name: "cover".to_owned(),
+                    old: None,
+                    new: Some("one".to_owned()),
+                },
+                Change::GuideChanged {
+                    old: vec![],
+                    new: vec![GuideEntry {
+                        kind: "text".to_owned(),
+                        title: "Start".to_owned(),
+                        href: "one.xhtml".to_owned(),
+                    }],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn embeds_into_the_package() {
+        let opf = package(ITEMS, &["one", "two"]);
+        let mut editor = Editor::new(Content::from_str(&opf).unwrap());
+        editor.set_language("fr");
+        let (mut content, changelog) = editor.finish();
+        changelog.embed(&mut content);
+
+        let mut editor = Editor::new(content);
+        editor.apply(|content| content.spine.refs.reverse());
+        let (mut content, changelog) = editor.finish();
+        assert_eq!(changelog.changes, vec![spine(&["one", "two"], &["two", "one"])]);
+        changelog.embed(&mut content);
+
+        let written = content.to_string().unwrap();
+        let content = Content::from_str(&written).unwrap();
+        let json = Changelog::embedded(&content);
+        assert_eq!(json[0]["field"], "language");
+        assert_eq!(json[1]["change"], "spine");
+        assert_eq!(json.len(), 2);
+    }
+}
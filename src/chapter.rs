@@ -0,0 +1,176 @@
+use anyhow::Result;
+
+use crate::storage::Storage;
+use crate::text::{self, XML_NAMESPACE};
+use crate::timeout::{Deadline, Timeout};
+use crate::{fingerprint, media_type, Content, Epub, Href, Item, Resource};
+
+/// Document of the spine together with the properties reading systems need to lay it out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chapter {
+    /// Id of the manifest item.
+    pub id: String,
+    /// Href of the document relative to the package document.
+    pub href: String,
+    /// Language declared on the body, or else on the root element, `None` if the document
+    /// doesn't declare one and the language of the book applies.
+    pub language: Option<String>,
+    /// Base text direction declared on the body, or else on the root element.
+    pub direction: Option<Direction>,
+    /// Hash of the text of the document, it changes with edits to the text
+    /// but not with changes to markup or packaging.
+    pub text_hash: u64,
+}
+
+impl Chapter {
+    /// Identifier of this revision of the chapter, combining the manifest id with the hash of its text.
+    pub fn stable_id(&self) -> String {
+        format!("{}-{:016x}", self.id, self.text_hash)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    Ltr,
+    Rtl,
+    Auto,
+}
+
+impl Direction {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "ltr" => Some(Direction::Ltr),
+            "rtl" => Some(Direction::Rtl),
+            "auto" => Some(Direction::Auto),
+            _ => None,
+        }
+    }
+}
+
+impl<S: Storage> Epub<S> {
+    /// Lists the XHTML documents of the spine in reading order.
+    /// Documents which can't be read or fail to parse are listed without language and direction.
+    pub fn chapters(&mut self, content: &Content) -> Result<Vec<Chapter>> {
+        let deadline = self.parse_deadline();
+        let mut chapters = vec![];
+        for document in self.spine_documents(content.spine_items(), deadline) {
+            let document = document?;
+            let mut chapter = Chapter {
+                id: document.item.id.clone().into_owned(),
+                href: document.href.as_ref().to_owned(),
+                language: None,
+                direction: None,
+                text_hash: text_hash(""),
+            };
+            if let Some(doc) = document.doc() {
+                chapter.text_hash = text_hash(&text::body_text(&doc));
+                let root = doc.root_element();
+                let body = text::body(&doc);
+                for node in [body, root] {
+                    if chapter.language.is_none() {
+                        chapter.language = node
+                            .attribute((XML_NAMESPACE, "lang"))
+                            .or_else(|| node.attribute("lang"))
+                            .map(str::trim)
+                            .filter(|lang| !lang.is_empty())
+                            .map(str::to_owned);
+                    }
+                    if chapter.direction.is_none() {
+                        chapter.direction = node.attribute("dir").and_then(Direction::parse);
+                    }
+                }
+            }
+            chapters.push(chapter);
+        }
+        Ok(chapters)
+    }
+
+    /// Reads the XHTML documents among `items`, in the given order, within `deadline`.
+    /// Items of other media types are passed over, documents which can't be read are yielded without
+    /// a resource so callers can decide whether to skip them, and running out of time ends with an error.
+    pub(crate) fn spine_documents<'c>(
+        &mut self,
+        items: impl IntoIterator<Item = &'c Item<'c>>,
+        deadline: Deadline,
+    ) -> SpineDocuments<'_, 'c, S> {
+        SpineDocuments {
+            book: self,
+            items: items.into_iter().collect::<Vec<_>>().into_iter(),
+            deadline,
+        }
+    }
+}
+
+/// Document of the spine read by [`Epub::spine_documents`].
+pub(crate) struct SpineDocument<'c> {
+    pub item: &'c Item<'c>,
+    pub href: Href<'c, media_type::XHtml>,
+    /// Contents of the document, `None` if it couldn't be read.
+    pub resource: Option<Resource<media_type::XHtml>>,
+}
+
+impl<'c> SpineDocument<'c> {
+    /// Parses the document, `None` if it couldn't be read or isn't well-formed.
+    pub fn doc(&self) -> Option<roxmltree::Document<'_>> {
+        self.resource.as_ref()?.doc().ok()
+    }
+}
+
+pub(crate) struct SpineDocuments<'e, 'c, S> {
+    book: &'e mut Epub<S>,
+    items: std::vec::IntoIter<&'c Item<'c>>,
+    deadline: Deadline,
+}
+
+impl<'e, 'c, S: Storage> Iterator for SpineDocuments<'e, 'c, S> {
+    type Item = Result<SpineDocument<'c>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for item in self.items.by_ref() {
+            if let Err(err) = self.deadline.check() {
+                return Some(Err(err.into()));
+            }
+            let href = match item.xhtml_href() {
+                Some(href) => href,
+                None => continue,
+            };
+            let resource = match self.book.read_within(href.clone(), self.deadline) {
+                Ok(resource) => Some(resource),
+                Err(err) if err.is::<Timeout>() => return Some(Err(err)),
+                Err(_) => None,
+            };
+            return Some(Ok(SpineDocument { item, href, resource }));
+        }
+        None
+    }
+}
+
+pub(crate) fn text_hash(text: &str) -> u64 {
+    fingerprint::fnv1a(fingerprint::FNV_OFFSET, text.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{book, package, xhtml};
+
+    #[test]
+    fn lists_unreadable_documents() {
+        let opf = package(
+            r#"<item id="one" href="one.xhtml" media-type="application/xhtml+xml"/>
+               <item id="two" href="two.xhtml" media-type="application/xhtml+xml"/>"#,
+            &["one", "two"],
+        );
+        let one = xhtml("<p>Un</p>").replace("<body>", r#"<body xml:lang="fr" dir="rtl">"#);
+        let mut book = book(&[("content.opf", &opf), ("one.xhtml", &one)]);
+        let resource = book.package().unwrap();
+        let chapters = book.chapters(&resource.content().unwrap()).unwrap();
+
+        assert_eq!(chapters.len(), 2);
+        assert_eq!(chapters[0].language.as_deref(), Some("fr"));
+        assert_eq!(chapters[0].direction, Some(Direction::Rtl));
+        assert_eq!(chapters[1].id, "two");
+        assert_eq!(chapters[1].language, None);
+        assert_eq!(chapters[1].text_hash, text_hash(""));
+    }
+}
@@ -0,0 +1,151 @@
+use std::borrow::Cow;
+
+use anyhow::{anyhow, Result};
+
+use crate::media_type::{self, MediaType};
+use crate::{path, Href, InvalidHref};
+
+/// Path of a file relative to the root of the container, like the paths in `META-INF/container.xml`
+/// and the names of the entries in the archive. Hrefs found in the package document and the NCX
+/// are relative to the package document instead and are represented by [`Href`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ContainerHref<'a> {
+    path: Cow<'a, str>,
+}
+
+/// [`Href`] relative to the package document, the alias makes the distinction from
+/// [`ContainerHref`] explicit where both are used.
+pub type PackageHref<'a, Media> = Href<'a, Media>;
+
+impl ContainerHref<'static> {
+    pub const CONTAINER: Self = Self::new(Cow::Borrowed("META-INF/container.xml"));
+    /// Location of the package document assumed when the container doesn't declare one.
+    pub const DEFAULT_PACKAGE: Self = Self::new(Cow::Borrowed("OEBPS/content.opf"));
+}
+
+impl<'a> ContainerHref<'a> {
+    const fn new(path: Cow<'a, str>) -> Self {
+        Self { path }
+    }
+
+    /// Wraps the name of an entry listed by the storage as it is, the entry exists under that name
+    /// even when it's not a valid path.
+    pub(crate) fn entry(name: impl Into<Cow<'a, str>>) -> Self {
+        Self::new(name.into())
+    }
+
+    /// Builds a container path, normalizing it and rejecting paths which can't point into the container.
+    pub fn try_new(path: impl Into<Cow<'a, str>>) -> Result<Self, InvalidHref> {
+        let path = path.into();
+        let normalized = path::validate(&path)?;
+        if normalized == path {
+            Ok(Self::new(path))
+        } else {
+            Ok(Self::new(Cow::Owned(normalized)))
+        }
+    }
+
+    /// Returns the directory part of the path, including the trailing slash.
+    pub fn directory(&self) -> &str {
+        path::parent(&self.path)
+    }
+
+    /// Resolves an href relative to the file at this path, dropping any fragment.
+    /// Returns `None` if the result would escape the root of the container.
+    pub fn join<Media>(&self, href: &Href<Media>) -> Option<ContainerHref<'static>> {
+        path::resolve(&self.path, href.as_ref()).map(|path| ContainerHref::new(Cow::Owned(path)))
+    }
+
+    /// Expresses this path relative to the package document at `package`,
+    /// the inverse of [`ContainerHref::join`].
+    pub fn relative_to<Media>(&self, package: &ContainerHref) -> Href<'static, Media> {
+        let directory = package.directory();
+        let url = match self.path.strip_prefix(directory) {
+            Some(rest) => rest.to_owned(),
+            None => {
+                let depth = directory.matches('/').count();
+                "../".repeat(depth) + &self.path
+            }
+        };
+        Href::new(Cow::Owned(url))
+    }
+
+    pub fn into_string(self) -> String {
+        self.path.into_owned()
+    }
+
+    /// Finds the package document listed by the contents of `META-INF/container.xml`.
+    pub(crate) fn rootfile(container: &[u8]) -> Result<ContainerHref<'static>> {
+        let text = std::str::from_utf8(container)?;
+        let doc = roxmltree::Document::parse(text)?;
+        let rootfiles: Vec<_> = doc
+            .descendants()
+            .filter(|node| node.tag_name().name() == "rootfile")
+            .collect();
+        let rootfile = rootfiles
+            .iter()
+            .find(|node| node.attribute("media-type") == Some(media_type::Opf::MEDIA_TYPE))
+            .or_else(|| rootfiles.first())
+            .ok_or_else(|| anyhow!("container lists no package document"))?;
+        let path = rootfile
+            .attribute("full-path")
+            .ok_or_else(|| anyhow!("rootfile is missing the full-path attribute"))?;
+        Ok(ContainerHref::try_new(path.to_owned())?)
+    }
+}
+
+impl<'a> AsRef<str> for ContainerHref<'a> {
+    fn as_ref(&self) -> &str {
+        self.path.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media_type;
+
+    #[test]
+    fn validates_paths() {
+        let href = ContainerHref::try_new("OEBPS/./text/../content.opf").unwrap();
+        assert_eq!(href.as_ref(), "OEBPS/content.opf");
+        assert_eq!(href.directory(), "OEBPS/");
+        assert_eq!(ContainerHref::try_new(""), Err(InvalidHref::Empty));
+        assert_eq!(ContainerHref::try_new("#top"), Err(InvalidHref::Empty));
+        assert_eq!(
+            ContainerHref::try_new("/OEBPS/content.opf"),
+            Err(InvalidHref::NotRelative)
+        );
+        assert_eq!(
+            ContainerHref::try_new("OEBPS\\content.opf"),
+            Err(InvalidHref::NotRelative)
+        );
+        assert_eq!(
+            ContainerHref::try_new("file:content.opf"),
+            Err(InvalidHref::NotRelative)
+        );
+        assert_eq!(ContainerHref::try_new("../content.opf"), Err(InvalidHref::EscapesRoot));
+        assert_eq!(ContainerHref::try_new("OEBPS/.."), Err(InvalidHref::Empty));
+    }
+
+    #[test]
+    fn converts_between_package_and_container_paths() {
+        let package = ContainerHref::try_new("OEBPS/content.opf").unwrap();
+        let href = Href::<media_type::XHtml>::try_new("text/one.xhtml#start").unwrap();
+        let path = package.join(&href).unwrap();
+        assert_eq!(path.as_ref(), "OEBPS/text/one.xhtml");
+        assert_eq!(
+            path.relative_to::<media_type::XHtml>(&package).as_ref(),
+            "text/one.xhtml"
+        );
+
+        let outside = ContainerHref::try_new("META-INF/cover.png").unwrap();
+        let href = outside.relative_to::<media_type::Png>(&package);
+        assert_eq!(href.as_ref(), "../META-INF/cover.png");
+        assert_eq!(package.join(&href), Some(outside));
+
+        let root = ContainerHref::try_new("content.opf").unwrap();
+        let nested = ContainerHref::try_new("a/b/c.png").unwrap();
+        assert_eq!(nested.relative_to::<media_type::Png>(&root).as_ref(), "a/b/c.png");
+    }
+}
@@ -0,0 +1,196 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::Result;
+
+use crate::storage::Storage;
+use crate::text::{self, XML_NAMESPACE};
+use crate::timeout::Timeout;
+use crate::{Content, Epub, Href};
+
+const FONT_MEDIA_TYPES: &[&str] = &[
+    "font/ttf",
+    "font/otf",
+    "font/sfnt",
+    "application/font-sfnt",
+    "application/x-font-ttf",
+    "application/x-font-truetype",
+    "application/x-font-opentype",
+    "application/vnd.ms-opentype",
+    "font/woff",
+    "font/woff2",
+    "application/font-woff",
+];
+
+/// Coarse classification of characters by writing system, derived from their Unicode block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Script {
+    /// Digits, punctuation, symbols and combining marks shared by many scripts.
+    Common,
+    Latin,
+    Greek,
+    Cyrillic,
+    Armenian,
+    Hebrew,
+    Arabic,
+    Devanagari,
+    Bengali,
+    Thai,
+    Georgian,
+    Hangul,
+    Hiragana,
+    Katakana,
+    Han,
+    Other,
+}
+
+impl Script {
+    pub fn of(char: char) -> Self {
+        if !char.is_alphabetic() {
+            return Script::Common;
+        }
+        match char as u32 {
+            0x0041..=0x024F
+            | 0x1E00..=0x1EFF
+            | 0x2C60..=0x2C7F
+            | 0xA720..=0xA7FF
+            | 0xAB30..=0xAB6F
+            | 0xFF21..=0xFF5A => Script::Latin,
+            0x0370..=0x03FF | 0x1F00..=0x1FFF => Script::Greek,
+            0x0400..=0x052F | 0x2DE0..=0x2DFF | 0xA640..=0xA69F => Script::Cyrillic,
+            0x0530..=0x058F => Script::Armenian,
+            0x0590..=0x05FF | 0xFB1D..=0xFB4F => Script::Hebrew,
+            0x0600..=0x06FF | 0x0750..=0x077F | 0x08A0..=0x08FF | 0xFB50..=0xFDFF | 0xFE70..=0xFEFF => Script::Arabic,
+            0x0900..=0x097F => Script::Devanagari,
+            0x0980..=0x09FF => Script::Bengali,
+            0x0E00..=0x0E7F => Script::Thai,
+            0x10A0..=0x10FF => Script::Georgian,
+            0x1100..=0x11FF | 0x3130..=0x318F | 0xAC00..=0xD7AF => Script::Hangul,
+            0x3040..=0x309F => Script::Hiragana,
+            0x30A0..=0x30FF | 0x31F0..=0x31FF => Script::Katakana,
+            0x2E80..=0x2FDF | 0x3005..=0x3007 | 0x3400..=0x4DBF | 0x4E00..=0x9FFF | 0xF900..=0xFAFF => Script::Han,
+            0x20000..=0x2FA1F => Script::Han,
+            _ => Script::Other,
+        }
+    }
+}
+
+/// Characters used by the text of a book checked against the fonts embedded in it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CoverageReport {
+    /// Characters used in the spine documents, grouped by the language of the element
+    /// they appear in and by script.
+    pub used: BTreeMap<(String, Script), BTreeSet<char>>,
+    /// Hrefs of the embedded fonts taken into account.
+    pub fonts: Vec<String>,
+    /// Hrefs of embedded fonts which could not be read, like missing, WOFF or obfuscated fonts.
+    pub unreadable_fonts: Vec<String>,
+    /// Characters which have no glyph in any of the embedded fonts, grouped like `used`.
+    pub missing: BTreeMap<(String, Script), BTreeSet<char>>,
+}
+
+impl<S: Storage> Epub<S> {
+    /// Collects the characters used by the spine documents and reports those which none
+    /// of the embedded fonts can display. Books without embedded fonts rely on the reading
+    /// system's fonts and report everything as missing.
+    pub fn font_coverage(&mut self, content: &Content) -> Result<CoverageReport> {
+        let deadline = self.parse_deadline();
+        let mut report = CoverageReport::default();
+
+        for document in self.spine_documents(content.spine_items(), deadline) {
+            let document = document?;
+            let doc = match document.doc() {
+                Some(doc) => doc,
+                None => continue,
+            };
+
+            for node in text::text_nodes(text::body(&doc)) {
+                let language = node
+                    .ancestors()
+                    .find_map(|node| {
+                        node.attribute((XML_NAMESPACE, "lang"))
+                            .or_else(|| node.attribute("lang"))
+                    })
+                    .unwrap_or(&content.metadata.language)
+                    .to_ascii_lowercase();
+                for char in node.text().unwrap_or_default().chars() {
+                    if char.is_whitespace() || char.is_control() {
+                        continue;
+                    }
+                    report
+                        .used
+                        .entry((language.clone(), Script::of(char)))
+                        .or_default()
+                        .insert(char);
+                }
+            }
+        }
+
+        let mut fonts = vec![];
+        for item in &content.manifest.items {
+            if !FONT_MEDIA_TYPES.contains(&item.media_type.as_ref()) {
+                continue;
+            }
+            match self.read_bytes(&Href::untyped(item.href.as_ref()), deadline) {
+                Ok(bytes) => fonts.push((item.href.as_ref(), Some(bytes))),
+                Err(err) if err.is::<Timeout>() => return Err(err),
+                Err(_) => fonts.push((item.href.as_ref(), None)),
+            }
+        }
+        let mut faces = vec![];
+        for (href, bytes) in &fonts {
+            match bytes.as_deref().map(|bytes| ttf_parser::Face::parse(bytes, 0)) {
+                Some(Ok(face)) => {
+                    faces.push(face);
+                    report.fonts.push(href.to_string());
+                }
+                _ => report.unreadable_fonts.push(href.to_string()),
+            }
+        }
+
+        for (key, chars) in &report.used {
+            let missing: BTreeSet<char> = chars
+                .iter()
+                .copied()
+                .filter(|char| !faces.iter().any(|face| face.glyph_index(*char).is_some()))
+                .collect();
+            if !missing.is_empty() {
+                report.missing.insert(key.clone(), missing);
+            }
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn missing_and_unparsable_fonts_are_unreadable() {
+        let package = testing::package(
+            r#"<item id="one" href="one.xhtml" media-type="application/xhtml+xml"/>
+               <item id="missing" href="fonts/missing.ttf" media-type="font/ttf"/>
+               <item id="woff" href="fonts/serif.woff" media-type="font/woff"/>"#,
+            &["one"],
+        );
+        let one = testing::xhtml(r#"<p>ab <span xml:lang="el">αβ</span></p><script>zz</script>"#);
+        let mut book = testing::book(&[
+            ("content.opf", &package),
+            ("one.xhtml", &one),
+            ("fonts/serif.woff", "wOFF"),
+        ]);
+        let resource = book.package().unwrap();
+        let content = resource.content().unwrap();
+        let report = book.font_coverage(&content).unwrap();
+
+        assert!(report.fonts.is_empty());
+        assert_eq!(report.unreadable_fonts, ["fonts/missing.ttf", "fonts/serif.woff"]);
+        let expected = BTreeMap::from([
+            (("en".to_owned(), Script::Latin), BTreeSet::from(['a', 'b'])),
+            (("el".to_owned(), Script::Greek), BTreeSet::from(['α', 'β'])),
+        ]);
+        assert_eq!(report.used, expected);
+        assert_eq!(report.missing, expected);
+    }
+}
@@ -0,0 +1,718 @@
+use std::borrow::Cow;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
+
+use anyhow::Result;
+
+use crate::storage::{NotFound, Storage};
+use crate::timeout::{Deadline, Timeout};
+use crate::{links, path, text, ContainerHref, Content, Epub, Href, NavEntry, NavPoint, TableOfContents};
+
+/// A structural problem found in a book.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Diagnostic {
+    /// More than one manifest item declares this id.
+    DuplicateItemId { id: String },
+    /// More than one manifest item points at this file.
+    DuplicateItemHref { href: String },
+    /// The spine references this item more than once.
+    DuplicateSpineRef { id_ref: String },
+    /// A navigation point targets a file outside of the manifest or a fragment missing from its document.
+    MissingNavTarget { href: String },
+    /// A table of contents entry is listed in only one of the NCX and the navigation document.
+    TocEntryMissing {
+        missing_from: TocSource,
+        title: String,
+        href: String,
+    },
+    /// The NCX and the navigation document give different titles to the same target.
+    TocTitleMismatch { href: String, ncx: String, nav: String },
+    /// The NCX and the navigation document list their common entries in a different order,
+    /// starting with the entry targeting this href.
+    TocOrderMismatch { href: String },
+    /// A document is not well-formed XML.
+    MalformedDocument { file: String, message: String },
+    /// A document links to a file that doesn't exist in the container.
+    BrokenLink { file: String, href: String },
+    /// The archive contains more than one file with this name, only one of them is used.
+    DuplicateEntry { name: String },
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Diagnostic::DuplicateItemId { id } => write!(f, "duplicate manifest item id '{}'", id),
+            Diagnostic::DuplicateItemHref { href } => write!(f, "duplicate manifest item href '{}'", href),
+            Diagnostic::DuplicateSpineRef { id_ref } => write!(f, "spine references '{}' more than once", id_ref),
+            Diagnostic::MissingNavTarget { href } => write!(f, "navigation point targets missing '{}'", href),
+            Diagnostic::TocEntryMissing {
+                missing_from,
+                title,
+                href,
+            } => write!(
+                f,
+                "toc entry '{}' targeting '{}' is missing from the {}",
+                title, href, missing_from
+            ),
+            Diagnostic::TocTitleMismatch { href, ncx, nav } => write!(
+                f,
+                "toc entry targeting '{}' is titled '{}' in the NCX and '{}' in the nav document",
+                href, ncx, nav
+            ),
+            Diagnostic::TocOrderMismatch { href } => write!(
+                f,
+                "NCX and nav document order their entries differently starting at '{}'",
+                href
+            ),
+            Diagnostic::MalformedDocument { file, message } => write!(f, "'{}' is malformed: {}", file, message),
+            Diagnostic::BrokenLink { file, href } => write!(f, "'{}' links to missing '{}'", file, href),
+            Diagnostic::DuplicateEntry { name } => write!(f, "archive contains more than one '{}'", name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TocSource {
+    Ncx,
+    Nav,
+}
+
+impl fmt::Display for TocSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TocSource::Ncx => f.write_str("NCX"),
+            TocSource::Nav => f.write_str("nav document"),
+        }
+    }
+}
+
+impl<'a> Content<'a> {
+    /// Reports manifest items sharing an id or href and items referenced more than once by the spine.
+    pub fn check_manifest(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = vec![];
+
+        let mut ids = HashSet::new();
+        let mut hrefs = HashSet::new();
+        for item in &self.manifest.items {
+            if !ids.insert(item.id.as_ref()) {
+                diagnostics.push(Diagnostic::DuplicateItemId {
+                    id: item.id.clone().into_owned(),
+                });
+            }
+            let href = path::normalize(&item.href).unwrap_or_else(|| item.href.clone().into_owned());
+            if !hrefs.insert(href) {
+                diagnostics.push(Diagnostic::DuplicateItemHref {
+                    href: item.href.clone().into_owned(),
+                });
+            }
+        }
+
+        let mut refs = HashSet::new();
+        for item_ref in &self.spine.refs {
+            if !refs.insert(item_ref.id_ref.as_ref()) {
+                diagnostics.push(Diagnostic::DuplicateSpineRef {
+                    id_ref: item_ref.id_ref.clone().into_owned(),
+                });
+            }
+        }
+        diagnostics
+    }
+
+    /// Lenient counterpart of [`Content::check_manifest`], it renames items with duplicate ids
+    /// to unique ones and drops repeated spine references. Spine references keep pointing at the
+    /// first item with a given id. Duplicate hrefs cannot be repaired and are only reported.
+    pub fn repair_manifest(&mut self) -> Vec<Diagnostic> {
+        let diagnostics = self.check_manifest();
+
+        let mut taken: HashSet<String> = self
+            .manifest
+            .items
+            .iter()
+            .map(|item| item.id.clone().into_owned())
+            .collect();
+        let mut seen = HashSet::new();
+        for item in &mut self.manifest.items {
+            if seen.insert(item.id.clone().into_owned()) {
+                continue;
+            }
+            let renamed = (2..)
+                .map(|suffix| format!("{}-{}", item.id, suffix))
+                .find(|id| !taken.contains(id))
+                .unwrap();
+            taken.insert(renamed.clone());
+            item.id = Cow::Owned(renamed);
+        }
+
+        let mut seen = HashSet::new();
+        self.spine
+            .refs
+            .retain(|item_ref| seen.insert(item_ref.id_ref.clone().into_owned()));
+
+        diagnostics
+    }
+}
+
+impl<S: Storage> Epub<S> {
+    /// Reads the package, the NCX table of contents, if the manifest lists one, and all documents
+    /// and reports the problems found in them, within the [`crate::timeout::Timeouts::validate`] budget.
+    /// A package or NCX that can't be parsed is reported as [`Diagnostic::MalformedDocument`].
+    pub fn validate(&mut self) -> Result<Vec<Diagnostic>> {
+        let deadline = self.validate_deadline();
+        let mut diagnostics: Vec<Diagnostic> = self
+            .storage
+            .duplicates()
+            .into_iter()
+            .map(|name| Diagnostic::DuplicateEntry { name })
+            .collect();
+        let resource = match self.package_within(deadline) {
+            Ok(resource) => resource,
+            Err(err) if err.is::<Timeout>() || err.is::<NotFound>() => return Err(err),
+            Err(err) => return self.malformed_package_within(diagnostics, err, deadline),
+        };
+        let content = match resource.content() {
+            Ok(content) => content,
+            Err(err) => return self.malformed_package_within(diagnostics, err, deadline),
+        };
+        diagnostics.extend(content.check_manifest());
+
+        if let Some(ncx_href) = content.ncx_href() {
+            match self.read_within(ncx_href.clone(), deadline) {
+                Ok(resource) => match resource.toc() {
+                    Ok(toc) => {
+                        diagnostics.extend(self.check_nav_targets_within(&content, &toc, deadline)?);
+                        diagnostics.extend(self.check_toc_consistency_within(&content, &toc, deadline)?);
+                    }
+                    Err(err) => diagnostics.push(Diagnostic::MalformedDocument {
+                        file: match self.resolve(&ncx_href) {
+                            Some(file) => file.into_string(),
+                            None => ncx_href.as_ref().to_owned(),
+                        },
+                        message: err.to_string(),
+                    }),
+                },
+                Err(err) if err.is::<Timeout>() => return Err(err),
+                Err(_) => {}
+            }
+        }
+        diagnostics.extend(self.check_documents_within(deadline)?);
+        Ok(diagnostics)
+    }
+
+    fn malformed_package_within(
+        &mut self,
+        mut diagnostics: Vec<Diagnostic>,
+        err: anyhow::Error,
+        deadline: Deadline,
+    ) -> Result<Vec<Diagnostic>> {
+        diagnostics.push(Diagnostic::MalformedDocument {
+            file: self.package.clone().into_string(),
+            message: err.to_string(),
+        });
+        diagnostics.extend(self.check_documents_within(deadline)?);
+        Ok(diagnostics)
+    }
+
+    /// Reports documents which are not well-formed or link to files missing from the container.
+    pub fn check_documents(&mut self) -> Result<Vec<Diagnostic>> {
+        let deadline = self.parse_deadline();
+        self.check_documents_within(deadline)
+    }
+
+    pub(crate) fn check_documents_within(&mut self, deadline: Deadline) -> Result<Vec<Diagnostic>> {
+        let files: BTreeSet<String> = self.storage.entries()?.into_iter().map(|entry| entry.name).collect();
+
+        let mut diagnostics = vec![];
+        for file in files.iter().filter(|file| is_document(file)) {
+            deadline.check()?;
+            let bytes = self.read_container_bytes(&ContainerHref::entry(file.as_str()), deadline)?;
+            diagnostics.extend(check_document(file, &bytes, |target| files.contains(target)).0);
+        }
+        Ok(diagnostics)
+    }
+
+    /// Reports navigation points targeting files that are not in the manifest
+    /// or fragment ids that don't exist in their document.
+    /// Srcs are resolved against the NCX listed in the manifest.
+    pub fn check_nav_targets(&mut self, content: &Content, toc: &TableOfContents) -> Result<Vec<Diagnostic>> {
+        let deadline = self.parse_deadline();
+        self.check_nav_targets_within(content, toc, deadline)
+    }
+
+    pub(crate) fn check_nav_targets_within(
+        &mut self,
+        content: &Content,
+        toc: &TableOfContents,
+        deadline: Deadline,
+    ) -> Result<Vec<Diagnostic>> {
+        let manifest: HashSet<String> = content
+            .manifest
+            .items
+            .iter()
+            .filter_map(|item| path::normalize(&item.href))
+            .collect();
+        let base = content.ncx_base();
+        let mut ids: HashMap<String, HashSet<String>> = HashMap::new();
+        let mut diagnostics = vec![];
+
+        let mut pending: Vec<&NavPoint> = toc.points().iter().rev().collect();
+        while let Some(point) = pending.pop() {
+            deadline.check()?;
+            pending.extend(point.children.iter().rev());
+
+            let src = point.content.src.as_ref();
+            let fragment = src.split_once('#').map(|(_, fragment)| fragment);
+            let found = match path::resolve(&base, src) {
+                Some(file) if manifest.contains(&file) => match fragment {
+                    Some(fragment) => {
+                        if !ids.contains_key(&file) {
+                            let document_ids = self.document_ids(&file, deadline)?;
+                            ids.insert(file.clone(), document_ids);
+                        }
+                        ids[&file].contains(fragment)
+                    }
+                    None => true,
+                },
+                _ => false,
+            };
+            if !found {
+                diagnostics.push(Diagnostic::MissingNavTarget { href: src.to_owned() });
+            }
+        }
+        Ok(diagnostics)
+    }
+
+    /// Compares the NCX with the EPUB 3 navigation document, if the book has both,
+    /// and reports entries missing from either, differing titles and differing order.
+    pub fn check_toc_consistency(&mut self, content: &Content, toc: &TableOfContents) -> Result<Vec<Diagnostic>> {
+        let deadline = self.parse_deadline();
+        self.check_toc_consistency_within(content, toc, deadline)
+    }
+
+    pub(crate) fn check_toc_consistency_within(
+        &mut self,
+        content: &Content,
+        toc: &TableOfContents,
+        deadline: Deadline,
+    ) -> Result<Vec<Diagnostic>> {
+        let nav_href = match content.nav_href() {
+            Some(href) => href,
+            None => return Ok(vec![]),
+        };
+        deadline.check()?;
+        let nav = match self
+            .read_within(nav_href.clone(), deadline)
+            .and_then(|resource| resource.nav())
+        {
+            Ok(nav) => nav,
+            Err(err) if err.is::<Timeout>() => return Err(err),
+            Err(err) if err.is::<NotFound>() => {
+                return Ok(vec![Diagnostic::MissingNavTarget {
+                    href: nav_href.as_ref().to_owned(),
+                }])
+            }
+            Err(err) => {
+                let file = match self.resolve(&nav_href) {
+                    Some(file) => file.into_string(),
+                    None => nav_href.as_ref().to_owned(),
+                };
+                return Ok(vec![Diagnostic::MalformedDocument {
+                    file,
+                    message: err.to_string(),
+                }]);
+            }
+        };
+
+        let base = content.ncx_base();
+        let mut ncx_entries = vec![];
+        let mut pending: Vec<&NavPoint> = toc.points().iter().rev().collect();
+        while let Some(point) = pending.pop() {
+            deadline.check()?;
+            pending.extend(point.children.iter().rev());
+            if let Some(target) = path::resolve_with_fragment(&base, &point.content.src) {
+                ncx_entries.push((text::collapse_whitespace(&point.label.text), target));
+            }
+        }
+        let mut nav_entries = vec![];
+        let mut pending: Vec<&NavEntry> = nav.entries.iter().rev().collect();
+        while let Some(entry) = pending.pop() {
+            deadline.check()?;
+            pending.extend(entry.children.iter().rev());
+            if let Some(target) = entry
+                .href
+                .as_ref()
+                .and_then(|href| path::resolve_with_fragment(nav_href.as_ref(), href))
+            {
+                nav_entries.push((entry.title.clone(), target));
+            }
+        }
+
+        let mut ncx_titles = HashMap::new();
+        for (title, target) in &ncx_entries {
+            ncx_titles.entry(target.as_str()).or_insert(title.as_str());
+        }
+        let mut nav_titles = HashMap::new();
+        for (title, target) in &nav_entries {
+            nav_titles.entry(target.as_str()).or_insert(title.as_str());
+        }
+
+        deadline.check()?;
+        let mut diagnostics = vec![];
+        for (title, target) in &ncx_entries {
+            match nav_titles.get(target.as_str()) {
+                Some(nav_title) if nav_title != title => diagnostics.push(Diagnostic::TocTitleMismatch {
+                    href: target.clone(),
+                    ncx: title.clone(),
+                    nav: nav_title.to_string(),
+                }),
+                Some(_) => {}
+                None => diagnostics.push(Diagnostic::TocEntryMissing {
+                    missing_from: TocSource::Nav,
+                    title: title.clone(),
+                    href: target.clone(),
+                }),
+            }
+        }
+        for (title, target) in &nav_entries {
+            if !ncx_titles.contains_key(target.as_str()) {
+                diagnostics.push(Diagnostic::TocEntryMissing {
+                    missing_from: TocSource::Ncx,
+                    title: title.clone(),
+                    href: target.clone(),
+                });
+            }
+        }
+
+        let common = |entries: &[(String, String)], other: &HashMap<&str, &str>| {
+            let mut seen = HashSet::new();
+            entries
+                .iter()
+                .map(|(_, target)| target.clone())
+                .filter(|target| other.contains_key(target.as_str()) && seen.insert(target.clone()))
+                .collect::<Vec<_>>()
+        };
+        deadline.check()?;
+        let ncx_order = common(&ncx_entries, &nav_titles);
+        let nav_order = common(&nav_entries, &ncx_titles);
+        if let Some((target, _)) = ncx_order.iter().zip(&nav_order).find(|(ncx, nav)| ncx != nav) {
+            diagnostics.push(Diagnostic::TocOrderMismatch { href: target.clone() });
+        }
+        Ok(diagnostics)
+    }
+
+    fn document_ids(&mut self, file: &str, deadline: Deadline) -> Result<HashSet<String>> {
+        let bytes = match self.read_bytes(&Href::untyped(file), deadline) {
+            Ok(bytes) => bytes,
+            Err(err) if err.is::<NotFound>() => return Ok(HashSet::new()),
+            Err(err) => return Err(err),
+        };
+        let text = match String::from_utf8(bytes) {
+            Ok(text) => text,
+            Err(_) => return Ok(HashSet::new()),
+        };
+        let ids = match roxmltree::Document::parse(&text) {
+            Ok(doc) => doc
+                .descendants()
+                .filter_map(|node| node.attribute("id"))
+                .map(str::to_owned)
+                .collect(),
+            Err(_) => HashSet::new(),
+        };
+        Ok(ids)
+    }
+}
+
+pub(crate) fn is_document(file: &str) -> bool {
+    let extension = file.rsplit_once('.').map(|(_, ext)| ext).unwrap_or_default();
+    matches!(
+        extension.to_ascii_lowercase().as_str(),
+        "xhtml" | "html" | "htm" | "svg"
+    )
+}
+
+/// Checks a single document, returning the problems found in it along with the files it links to.
+pub(crate) fn check_document(
+    file: &str,
+    bytes: &[u8],
+    exists: impl Fn(&str) -> bool,
+) -> (Vec<Diagnostic>, HashSet<String>) {
+    let malformed = |message: String| Diagnostic::MalformedDocument {
+        file: file.to_owned(),
+        message,
+    };
+    let text = match std::str::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(err) => return (vec![malformed(err.to_string())], HashSet::new()),
+    };
+    let doc = match roxmltree::Document::parse(text) {
+        Ok(doc) => doc,
+        Err(err) => return (vec![malformed(err.to_string())], HashSet::new()),
+    };
+
+    let mut diagnostics = vec![];
+    let mut targets = HashSet::new();
+    for url in links::document_links(&doc) {
+        match path::resolve(file, url) {
+            Some(target) if target.is_empty() || exists(&target) => {
+                targets.insert(target);
+            }
+            Some(target) => {
+                diagnostics.push(Diagnostic::BrokenLink {
+                    file: file.to_owned(),
+                    href: url.to_owned(),
+                });
+                targets.insert(target);
+            }
+            None => diagnostics.push(Diagnostic::BrokenLink {
+                file: file.to_owned(),
+                href: url.to_owned(),
+            }),
+        }
+    }
+    (diagnostics, targets)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::testing;
+    use crate::timeout::{Operation, Timeouts};
+
+    #[test]
+    fn validate_runs_within_its_own_budget() {
+        let package = testing::package(
+            r#"<item id="one" href="one.xhtml" media-type="application/xhtml+xml"/>"#,
+            &["one"],
+        );
+        let one = testing::xhtml("<p>One</p>");
+        let mut book = testing::book(&[("content.opf", &package), ("one.xhtml", &one)]);
+        assert_eq!(book.validate().unwrap(), vec![]);
+
+        book.timeouts = Timeouts {
+            parse: Some(Duration::from_secs(3600)),
+            validate: Some(Duration::ZERO),
+            ..Timeouts::default()
+        };
+        let err = book.validate().unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<Timeout>(),
+            Some(&Timeout {
+                operation: Operation::Validate
+            })
+        );
+        assert!(book.check_documents().is_ok());
+    }
+
+    #[test]
+    fn toc_consistency_reports_divergences_within_the_deadline() {
+        let package = testing::package(
+            r#"<item id="ncx" href="toc.ncx" media-type="application/x-dtbncx+xml"/>
+               <item id="nav" href="text/nav.xhtml" media-type="application/xhtml+xml" properties="nav"/>
+               <item id="one" href="text/one.xhtml" media-type="application/xhtml+xml"/>
+               <item id="two" href="text/two.xhtml" media-type="application/xhtml+xml"/>"#,
+            &["one", "two"],
+        );
+        let ncx = r#"<ncx xmlns="http://www.daisy.org/z3986/2005/ncx/" version="2005-1"><navMap>
+            <navPoint><navLabel><text>Two</text></navLabel><content src="text/two.xhtml"/></navPoint>
+            <navPoint><navLabel><text>One</text></navLabel><content src="text/one.xhtml"/></navPoint>
+            </navMap></ncx>"#;
+        let nav = testing::xhtml(
+            r#"<nav><ol><li><a href="one.xhtml">Chapter One</a></li><li><a href="two.xhtml">Two</a></li></ol></nav>"#,
+        );
+        let mut book = testing::book(&[("content.opf", &package), ("toc.ncx", ncx), ("text/nav.xhtml", &nav)]);
+        let resource = book.package().unwrap();
+        let content = resource.content().unwrap();
+        let resource = book.read(content.ncx_href().unwrap()).unwrap();
+        let toc = resource.toc().unwrap();
+        assert_eq!(
+            book.check_toc_consistency(&content, &toc).unwrap(),
+            vec![
+                Diagnostic::TocTitleMismatch {
+                    href: "text/one.xhtml".to_owned(),
+                    ncx: "One".to_owned(),
+                    nav: "Chapter One".to_owned(),
+                },
+                Diagnostic::TocOrderMismatch {
+                    href: "text/two.xhtml".to_owned()
+                },
+            ]
+        );
+
+        book.timeouts.parse = Some(Duration::ZERO);
+        let err = book.check_toc_consistency(&content, &toc).unwrap_err();
+        assert!(err.is::<Timeout>());
+    }
+
+    #[test]
+    fn ncx_is_located_through_the_manifest() {
+        let package = testing::package(
+            r#"<item id="ncx" href="meta/contents.ncx" media-type="application/x-dtbncx+xml"/>
+               <item id="one" href="text/one.xhtml" media-type="application/xhtml+xml"/>"#,
+            &["one"],
+        );
+        let ncx = r#"<ncx xmlns="http://www.daisy.org/z3986/2005/ncx/" version="2005-1"><navMap>
+            <navPoint><navLabel><text>One</text></navLabel><content src="../text/one.xhtml#start"/></navPoint>
+            <navPoint><navLabel><text>Two</text></navLabel><content src="../text/two.xhtml"/></navPoint>
+            <navPoint><navLabel><text>End</text></navLabel><content src="../text/one.xhtml#end"/></navPoint>
+            </navMap></ncx>"#;
+        let one = testing::xhtml(r#"<h1 id="start">One</h1>"#);
+        let mut book = testing::book(&[
+            ("content.opf", &package),
+            ("meta/contents.ncx", ncx),
+            ("text/one.xhtml", &one),
+        ]);
+        assert_eq!(
+            book.validate().unwrap(),
+            vec![
+                Diagnostic::MissingNavTarget {
+                    href: "../text/two.xhtml".to_owned()
+                },
+                Diagnostic::MissingNavTarget {
+                    href: "../text/one.xhtml#end".to_owned()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn documents_are_checked_in_name_order() {
+        let names = ["c.xhtml", "a.xhtml", "d.xhtml", "b.xhtml"];
+        let items: String = names
+            .iter()
+            .map(|name| {
+                format!(
+                    r#"<item id="{0}" href="{0}" media-type="application/xhtml+xml"/>"#,
+                    name
+                )
+            })
+            .collect();
+        let package = testing::package(&items, &names);
+        let page = testing::xhtml(r#"<a href="missing.xhtml">Missing</a>"#);
+        let mut files = vec![("content.opf", package.as_str())];
+        files.extend(names.iter().map(|name| (*name, page.as_str())));
+        let mut book = testing::book(&files);
+
+        let files: Vec<String> = book
+            .validate()
+            .unwrap()
+            .into_iter()
+            .map(|diagnostic| match diagnostic {
+                Diagnostic::BrokenLink { file, .. } => file,
+                other => panic!("unexpected {:?}", other),
+            })
+            .collect();
+        assert_eq!(
+            files,
+            ["OEBPS/a.xhtml", "OEBPS/b.xhtml", "OEBPS/c.xhtml", "OEBPS/d.xhtml"]
+        );
+    }
+
+    #[test]
+    fn unusable_nav_documents_are_reported() {
+        let package = testing::package(
+            r#"<item id="ncx" href="toc.ncx" media-type="application/x-dtbncx+xml"/>
+               <item id="nav" href="nav.xhtml" media-type="application/xhtml+xml" properties="nav"/>
+               <item id="one" href="one.xhtml" media-type="application/xhtml+xml"/>"#,
+            &["one"],
+        );
+        let ncx = r#"<ncx xmlns="http://www.daisy.org/z3986/2005/ncx/" version="2005-1"><navMap>
+            <navPoint><navLabel><text>One</text></navLabel><content src="one.xhtml"/></navPoint>
+            </navMap></ncx>"#;
+        let one = testing::xhtml("<p>One</p>");
+        let mut book = testing::book(&[("content.opf", &package), ("toc.ncx", ncx), ("one.xhtml", &one)]);
+        assert_eq!(
+            book.validate().unwrap(),
+            vec![Diagnostic::MissingNavTarget {
+                href: "nav.xhtml".to_owned()
+            }]
+        );
+
+        let without_nav = testing::xhtml("<p>Contents</p>");
+        book.storage.insert("OEBPS/nav.xhtml", without_nav.into_bytes());
+        assert_eq!(
+            book.validate().unwrap(),
+            vec![Diagnostic::MalformedDocument {
+                file: "OEBPS/nav.xhtml".to_owned(),
+                message: "document has no nav element".to_owned(),
+            }]
+        );
+
+        book.storage.insert("OEBPS/nav.xhtml", b"<nav>\xff</nav>".to_vec());
+        let diagnostics = book.validate().unwrap();
+        assert_eq!(diagnostics.len(), 2);
+        assert!(diagnostics.iter().all(|diagnostic| matches!(
+            diagnostic,
+            Diagnostic::MalformedDocument { file, .. } if file == "OEBPS/nav.xhtml"
+        )));
+    }
+
+    #[test]
+    fn manifest_duplicates_are_reported_and_repaired() {
+        let package = testing::package(
+            r#"<item id="one" href="text/one.xhtml" media-type="application/xhtml+xml"/>
+               <item id="one" href="text/two.xhtml" media-type="application/xhtml+xml"/>
+               <item id="one-2" href="text/three.xhtml" media-type="application/xhtml+xml"/>
+               <item id="copy" href="text/../text/one.xhtml" media-type="application/xhtml+xml"/>"#,
+            &["one", "one-2", "one"],
+        );
+        let mut book = testing::book(&[("content.opf", &package)]);
+        let resource = book.package().unwrap();
+        let mut content = resource.content().unwrap();
+        let expected = vec![
+            Diagnostic::DuplicateItemId { id: "one".to_owned() },
+            Diagnostic::DuplicateItemHref {
+                href: "text/../text/one.xhtml".to_owned(),
+            },
+            Diagnostic::DuplicateSpineRef {
+                id_ref: "one".to_owned(),
+            },
+        ];
+        assert_eq!(content.check_manifest(), expected);
+
+        assert_eq!(content.repair_manifest(), expected);
+        let ids: Vec<&str> = content.manifest.items.iter().map(|item| item.id.as_ref()).collect();
+        assert_eq!(ids, ["one", "one-3", "one-2", "copy"]);
+        let refs: Vec<&str> = content
+            .spine
+            .refs
+            .iter()
+            .map(|item_ref| item_ref.id_ref.as_ref())
+            .collect();
+        assert_eq!(refs, ["one", "one-2"]);
+        assert_eq!(
+            content.check_manifest(),
+            vec![Diagnostic::DuplicateItemHref {
+                href: "text/../text/one.xhtml".to_owned(),
+            }]
+        );
+    }
+
+    #[test]
+    fn malformed_packages_and_ncx_are_reported() {
+        let package = testing::package(
+            r#"<item id="ncx" href="toc.ncx" media-type="application/x-dtbncx+xml"/>
+               <item id="one" href="one.xhtml" media-type="application/xhtml+xml"/>"#,
+            &["one"],
+        );
+        let one = testing::xhtml("<p>One</p>");
+        let mut book = testing::book(&[
+            ("content.opf", &package),
+            ("toc.ncx", "<ncx><navMap>"),
+            ("one.xhtml", &one),
+        ]);
+        let diagnostics = book.validate().unwrap();
+        assert!(matches!(
+            diagnostics.as_slice(),
+            [Diagnostic::MalformedDocument { file, .. }] if file == "OEBPS/toc.ncx"
+        ));
+
+        book.storage
+            .insert("OEBPS/content.opf", package.as_bytes()[..package.len() / 2].to_vec());
+        let diagnostics = book.validate().unwrap();
+        assert!(matches!(
+            diagnostics.as_slice(),
+            [Diagnostic::MalformedDocument { file, .. }] if file == "OEBPS/content.opf"
+        ));
+    }
+}
@@ -0,0 +1,297 @@
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+use std::io::{Seek, Write};
+
+use anyhow::{anyhow, Result};
+use strong_xml::XmlWrite;
+use zip::write::FileOptions;
+use zip::CompressionMethod;
+
+use crate::archive::{ArchiveMetadata, ArchiveWriter};
+use crate::storage::{NotFound, Storage};
+use crate::{
+    links, outline, path, Content, Epub, Guide, Href, Item, ItemRef, Manifest, Metadata, NavMap, NavPoint, Spine,
+    TableOfContents, PACKAGE_DIR,
+};
+
+impl<S: Storage> Epub<S> {
+    /// Writes a standalone book containing only the section of `toc` which starts at `point`.
+    /// The section spans the spine documents from the one the point targets up to the one
+    /// targeted by the next entry on the same or a higher level, together with the stylesheets,
+    /// images and media they use. Hyperlinks are not followed, documents outside of the section
+    /// are left out even when linked to.
+    pub fn extract_section<W: Write + Seek>(
+        &mut self,
+        content: &Content,
+        toc: &TableOfContents,
+        point: &NavPoint,
+        output: W,
+    ) -> Result<()> {
+        self.extract_section_with(content, toc, point, output, &ArchiveMetadata::default())
+    }
+
+    /// Like [`Epub::extract_section`], but writes the archive comment and the timestamps and comments
+    /// of the copied files from `metadata`, for example the one returned by [`crate::storage::ZipStorage::metadata`].
+    pub fn extract_section_with<W: Write + Seek>(
+        &mut self,
+        content: &Content,
+        toc: &TableOfContents,
+        point: &NavPoint,
+        output: W,
+        metadata: &ArchiveMetadata,
+    ) -> Result<()> {
+        let deadline = self.parse_deadline();
+        let next = next_section(toc.points(), point, None).ok_or_else(|| anyhow!("nav point is not in the toc"))?;
+
+        let base = content.ncx_base();
+        let spine: Vec<&Item> = content.spine_items().collect();
+        let position = |point: &NavPoint| {
+            let target = path::resolve(&base, &point.content.src);
+            spine.iter().position(|item| path::normalize(&item.href) == target)
+        };
+        let start = position(point).ok_or_else(|| anyhow!("nav point does not target a spine document"))?;
+        let end = match next {
+            Some(next) => position(next).filter(|end| *end > start).unwrap_or(start + 1),
+            None => spine.len(),
+        };
+
+        let mut included: BTreeSet<String> = BTreeSet::new();
+        let mut pending: Vec<String> = spine[start..end]
+            .iter()
+            .filter_map(|item| path::normalize(&item.href))
+            .collect();
+        while let Some(file) = pending.pop() {
+            deadline.check()?;
+            let item = match content
+                .manifest
+                .items
+                .iter()
+                .find(|item| path::normalize(&item.href).as_ref() == Some(&file))
+            {
+                Some(item) => item,
+                None => continue,
+            };
+            if !included.insert(file.clone()) {
+                continue;
+            }
+
+            let bytes = self.read_bytes(&Href::untyped(file.as_str()), deadline)?;
+            let text = match std::str::from_utf8(&bytes) {
+                Ok(text) => text,
+                Err(_) => continue,
+            };
+            let urls: Vec<String> = match item.media_type.as_ref() {
+                "application/xhtml+xml" | "image/svg+xml" => match roxmltree::Document::parse(text) {
+                    Ok(doc) => links::resource_links(&doc).into_iter().map(str::to_owned).collect(),
+                    Err(_) => vec![],
+                },
+                "text/css" => links::css_links(text).into_iter().map(str::to_owned).collect(),
+                _ => vec![],
+            };
+            pending.extend(urls.iter().filter_map(|url| path::resolve(&file, url)));
+        }
+
+        let items: Vec<Item> = content
+            .manifest
+            .items
+            .iter()
+            .filter(|item| path::normalize(&item.href).is_some_and(|file| included.contains(&file)))
+            .map(|item| Item {
+                id: Cow::Borrowed(&item.id),
+                media_type: Cow::Borrowed(&item.media_type),
+                href: Cow::Borrowed(&item.href),
+                properties: item.properties.as_deref().map(Cow::Borrowed),
+            })
+            .chain(Some(Item {
+                id: Cow::Borrowed("ncx"),
+                media_type: Cow::Borrowed("application/x-dtbncx+xml"),
+                href: Href::TOC.url,
+                properties: None,
+            }))
+            .collect();
+        let refs = spine[start..end]
+            .iter()
+            .map(|item| ItemRef {
+                id_ref: Cow::Borrowed(&item.id),
+            })
+            .collect();
+
+        let package = Content {
+            xmlns: Some(Cow::Borrowed(Content::NAMESPACE)),
+            version: Some(Cow::Borrowed(Content::VERSION)),
+            unique_identifier: content.unique_identifier.clone(),
+            metadata: Metadata {
+                xmlns_dc: Some(Cow::Borrowed(Metadata::DC_NAMESPACE)),
+                xmlns_opf: Some(Cow::Borrowed(Content::NAMESPACE)),
+                title: Cow::Owned(point.label.text.trim().to_owned()),
+                language: Cow::Borrowed(&content.metadata.language),
+                identifier: Cow::Borrowed(&content.metadata.identifier),
+                meta: vec![],
+            },
+            manifest: Manifest { items },
+            spine: Spine {
+                toc: Some(Cow::Borrowed("ncx")),
+                refs,
+            },
+            guide: Guide { references: vec![] },
+        };
+        let section_toc = TableOfContents {
+            xmlns: Some(Cow::Borrowed(TableOfContents::NAMESPACE)),
+            version: Some(Cow::Borrowed(TableOfContents::VERSION)),
+            head: None,
+            map: NavMap {
+                points: vec![outline::rebased_point(point, &base)],
+            },
+        };
+
+        let mut zip = ArchiveWriter::new(output, metadata);
+        let stored = FileOptions::default().compression_method(CompressionMethod::Stored);
+        let deflated = FileOptions::default();
+
+        zip.start_file("mimetype", stored)?;
+        zip.write_all(b"application/epub+zip")?;
+        zip.start_file("META-INF/container.xml", deflated)?;
+        zip.write_all(container_xml().as_bytes())?;
+        zip.start_file(&(PACKAGE_DIR.to_owned() + Href::CONTENT.as_ref()), deflated)?;
+        zip.write_all(XML_DECLARATION.as_bytes())?;
+        zip.write_all(package.to_string()?.as_bytes())?;
+        zip.start_file(&(PACKAGE_DIR.to_owned() + Href::TOC.as_ref()), deflated)?;
+        zip.write_all(XML_DECLARATION.as_bytes())?;
+        zip.write_all(section_toc.to_string()?.as_bytes())?;
+
+        for file in &included {
+            let source = self
+                .resolve(&Href::untyped(file.as_str()))
+                .ok_or_else(|| NotFound { name: file.clone() })?;
+            let bytes = self.read_container_bytes(&source, deadline)?;
+            zip.start_file_from(source.as_ref(), &(PACKAGE_DIR.to_owned() + file), deflated)?;
+            zip.write_all(&bytes)?;
+        }
+        zip.finish()
+    }
+}
+
+pub(crate) const XML_DECLARATION: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n";
+
+pub(crate) fn container_xml() -> String {
+    format!(
+        "{}<container version=\"1.0\" xmlns=\"urn:oasis:names:tc:opendocument:xmlns:container\">\
+         <rootfiles><rootfile full-path=\"{}{}\" media-type=\"application/oebps-package+xml\"/></rootfiles>\
+         </container>",
+        XML_DECLARATION,
+        PACKAGE_DIR,
+        Href::CONTENT.as_ref()
+    )
+}
+
+/// Finds the entry following `target` on the same or a higher level of the tree.
+/// Returns `None` if the target is not found and `Some(None)` if it's the last section of the book.
+fn next_section<'t, 'a>(
+    points: &'t [NavPoint<'a>],
+    target: &NavPoint,
+    after: Option<&'t NavPoint<'a>>,
+) -> Option<Option<&'t NavPoint<'a>>> {
+    for (idx, point) in points.iter().enumerate() {
+        let next = points.get(idx + 1).or(after);
+        if std::ptr::eq(point as *const _ as *const (), target as *const _ as *const ()) {
+            return Some(next);
+        }
+        if let Some(found) = next_section(&point.children, target, next) {
+            return Some(found);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::archive::EntryMetadata;
+    use crate::testing;
+
+    #[test]
+    fn section_includes_resources_but_not_linked_chapters() {
+        let package = testing::package(
+            r#"<item id="ncx" href="toc.ncx" media-type="application/x-dtbncx+xml"/>
+               <item id="one" href="text/one.xhtml" media-type="application/xhtml+xml"/>
+               <item id="two" href="text/two.xhtml" media-type="application/xhtml+xml"/>
+               <item id="css" href="style/book.css" media-type="text/css"/>
+               <item id="font" href="fonts/serif.otf" media-type="font/otf"/>
+               <item id="cover" href="images/cover.png" media-type="image/png"/>"#,
+            &["one", "two"],
+        );
+        let ncx = r#"<ncx xmlns="http://www.daisy.org/z3986/2005/ncx/" version="2005-1"><navMap>
+            <navPoint><navLabel><text>One</text></navLabel><content src="text/one.xhtml"/></navPoint>
+            <navPoint><navLabel><text>Two</text></navLabel><content src="text/two.xhtml"/></navPoint>
+            </navMap></ncx>"#;
+        let one = r#"<html xmlns="http://www.w3.org/1999/xhtml"><head><title>One</title>
+            <link rel="stylesheet" href="../style/book.css"/></head>
+            <body><img src="../images/cover.png"/><p><a href="two.xhtml">Next chapter</a></p></body></html>"#;
+        let two = testing::xhtml("<p>Two</p>");
+        let css = "@font-face { src: url('../fonts/serif.otf') }";
+        let mut book = testing::book(&[
+            ("content.opf", &package),
+            ("toc.ncx", ncx),
+            ("text/one.xhtml", one),
+            ("text/two.xhtml", &two),
+            ("style/book.css", css),
+            ("fonts/serif.otf", "font"),
+            ("images/cover.png", "png"),
+        ]);
+        let resource = book.package().unwrap();
+        let content = resource.content().unwrap();
+        let resource = book.read(content.ncx_href().unwrap()).unwrap();
+        let toc = resource.toc().unwrap();
+
+        let mut output = Cursor::new(vec![]);
+        book.extract_section(&content, &toc, &toc.points()[0], &mut output)
+            .unwrap();
+
+        let mut section = Epub::new(Cursor::new(output.into_inner())).unwrap();
+        let names: Vec<String> = section
+            .storage
+            .entries()
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "mimetype",
+                "META-INF/container.xml",
+                "OEBPS/content.opf",
+                "OEBPS/toc.ncx",
+                "OEBPS/fonts/serif.otf",
+                "OEBPS/images/cover.png",
+                "OEBPS/style/book.css",
+                "OEBPS/text/one.xhtml",
+            ]
+        );
+        let section_package = section.package().unwrap();
+        let section_content = section_package.content().unwrap();
+        let spine: Vec<&str> = section_content.spine_items().map(|item| item.id.as_ref()).collect();
+        assert_eq!(spine, ["one"]);
+
+        let mut metadata = ArchiveMetadata::default();
+        metadata.entries.insert(
+            "OEBPS/images/cover.png".to_owned(),
+            EntryMetadata {
+                comment: "photo by the author".to_owned(),
+                ..EntryMetadata::default()
+            },
+        );
+        let mut output = Cursor::new(vec![]);
+        book.extract_section_with(&content, &toc, &toc.points()[0], &mut output, &metadata)
+            .unwrap();
+        let mut section = Epub::new(Cursor::new(output.into_inner())).unwrap();
+        let written = section.archive_metadata().unwrap();
+        assert_eq!(
+            written.entries["OEBPS/images/cover.png"],
+            metadata.entries["OEBPS/images/cover.png"]
+        );
+        assert_eq!(written.entries["OEBPS/text/one.xhtml"].comment, "");
+    }
+}
@@ -0,0 +1,305 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::{Seek, Write};
+
+use anyhow::Result;
+use strong_xml::XmlWrite;
+use zip::write::FileOptions;
+use zip::CompressionMethod;
+
+use crate::archive::{ArchiveMetadata, ArchiveWriter};
+use crate::extract::{container_xml, XML_DECLARATION};
+use crate::media_type::{self, MediaType};
+use crate::{
+    html, Content, Guide, Href, Item, ItemRef, Manifest, Metadata, NavContent, NavLabel, NavMap, NavPoint, Spine,
+    TableOfContents, PACKAGE_DIR,
+};
+
+const XHTML_NAMESPACE: &str = "http://www.w3.org/1999/xhtml";
+
+/// Feed converted into a periodical-style book, with a chapter per entry
+/// and a table of contents grouping the entries by the day they were published.
+#[derive(Debug, Clone, Default)]
+pub struct Feed {
+    pub title: String,
+    pub language: String,
+    /// Unique identifier of the issue, like a url of the feed with the date appended.
+    pub identifier: String,
+    pub entries: Vec<FeedEntry>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FeedEntry {
+    pub title: String,
+    pub author: Option<String>,
+    /// Publication time in the RFC 3339 format used by Atom, only the date part is used for grouping.
+    pub published: Option<String>,
+    /// Body of the entry as HTML. It's converted to XHTML keeping the text, links, images
+    /// and basic formatting, while scripts, styles and any other markup are dropped.
+    pub content: String,
+    pub images: Vec<FeedImage>,
+}
+
+/// Image embedded in the book, references to its url in the content are rewritten to point at the copy.
+#[derive(Debug, Clone, Default)]
+pub struct FeedImage {
+    pub url: String,
+    pub media_type: String,
+    pub data: Vec<u8>,
+}
+
+impl Feed {
+    pub fn write_epub<W: Write + Seek>(&self, output: W) -> Result<()> {
+        self.write_epub_with(output, &ArchiveMetadata::default())
+    }
+
+    /// Like [`Feed::write_epub`], but writes the archive comment of `metadata` and the timestamps and comments
+    /// it lists for the files, keyed by their path in the container, like `OEBPS/articles/article-1.xhtml`.
+    pub fn write_epub_with<W: Write + Seek>(&self, output: W, metadata: &ArchiveMetadata) -> Result<()> {
+        let mut sections: Vec<(&str, Vec<usize>)> = vec![];
+        for (idx, entry) in self.entries.iter().enumerate() {
+            let date = entry
+                .published
+                .as_deref()
+                .map(|published| published.get(..10).unwrap_or(published))
+                .unwrap_or("Undated");
+            match sections.iter_mut().find(|(section, _)| *section == date) {
+                Some((_, entries)) => entries.push(idx),
+                None => sections.push((date, vec![idx])),
+            }
+        }
+
+        let mut zip = ArchiveWriter::new(output, metadata);
+        let stored = FileOptions::default().compression_method(CompressionMethod::Stored);
+        let deflated = FileOptions::default();
+        zip.start_file("mimetype", stored)?;
+        zip.write_all(b"application/epub+zip")?;
+        zip.start_file("META-INF/container.xml", deflated)?;
+        zip.write_all(container_xml().as_bytes())?;
+
+        let mut items = vec![Item {
+            id: Cow::Borrowed("ncx"),
+            media_type: Cow::Borrowed("application/x-dtbncx+xml"),
+            href: Href::TOC.url,
+            properties: None,
+        }];
+        let mut refs = vec![];
+        let mut points = vec![];
+        let mut order = 0;
+        let mut point = |title: &str, src: &str| {
+            order += 1;
+            NavPoint {
+                id: Some(Cow::Owned(format!("navPoint-{}", order))),
+                play_order: Some(Cow::Owned(order.to_string())),
+                label: NavLabel {
+                    text: Cow::Owned(title.to_owned()),
+                },
+                content: NavContent {
+                    src: Cow::Owned(src.to_owned()),
+                },
+                children: vec![],
+            }
+        };
+
+        for (date, entries) in &sections {
+            let mut section = point(date, &article_href(entries[0]));
+            for &idx in entries {
+                let entry = &self.entries[idx];
+                let href = article_href(idx);
+                let mut images = HashMap::new();
+                for (image_idx, image) in entry.images.iter().enumerate() {
+                    let image_href = format!(
+                        "images/article-{}-{}.{}",
+                        idx + 1,
+                        image_idx + 1,
+                        extension(&image.media_type)
+                    );
+                    images.insert(image.url.as_str(), format!("../{}", image_href));
+                    zip.start_file(&(PACKAGE_DIR.to_owned() + &image_href), deflated)?;
+                    zip.write_all(&image.data)?;
+                    items.push(Item {
+                        id: Cow::Owned(format!("image-{}-{}", idx + 1, image_idx + 1)),
+                        media_type: Cow::Borrowed(&image.media_type),
+                        href: Cow::Owned(image_href),
+                        properties: None,
+                    });
+                }
+
+                zip.start_file(&(PACKAGE_DIR.to_owned() + &href), deflated)?;
+                zip.write_all(article(entry, &images).as_bytes())?;
+                let id = format!("article-{}", idx + 1);
+                refs.push(ItemRef {
+                    id_ref: Cow::Owned(id.clone()),
+                });
+                items.push(Item {
+                    id: Cow::Owned(id),
+                    media_type: Cow::Borrowed(media_type::XHtml::MEDIA_TYPE),
+                    href: Cow::Owned(href.clone()),
+                    properties: None,
+                });
+                section.children.push(point(&entry.title, &href));
+            }
+            points.push(section);
+        }
+
+        let package = Content {
+            xmlns: Some(Cow::Borrowed(Content::NAMESPACE)),
+            version: Some(Cow::Borrowed(Content::VERSION)),
+            unique_identifier: None,
+            metadata: Metadata {
+                xmlns_dc: Some(Cow::Borrowed(Metadata::DC_NAMESPACE)),
+                xmlns_opf: Some(Cow::Borrowed(Content::NAMESPACE)),
+                title: Cow::Borrowed(&self.title),
+                language: Cow::Borrowed(&self.language),
+                identifier: Cow::Borrowed(&self.identifier),
+                meta: vec![],
+            },
+            manifest: Manifest { items },
+            spine: Spine {
+                toc: Some(Cow::Borrowed("ncx")),
+                refs,
+            },
+            guide: Guide { references: vec![] },
+        };
+        let toc = TableOfContents {
+            xmlns: Some(Cow::Borrowed(TableOfContents::NAMESPACE)),
+            version: Some(Cow::Borrowed(TableOfContents::VERSION)),
+            head: None,
+            map: NavMap { points },
+        };
+
+        zip.start_file(&(PACKAGE_DIR.to_owned() + Href::CONTENT.as_ref()), deflated)?;
+        zip.write_all(XML_DECLARATION.as_bytes())?;
+        zip.write_all(package.to_string()?.as_bytes())?;
+        zip.start_file(&(PACKAGE_DIR.to_owned() + Href::TOC.as_ref()), deflated)?;
+        zip.write_all(XML_DECLARATION.as_bytes())?;
+        zip.write_all(toc.to_string()?.as_bytes())?;
+        zip.finish()?;
+        Ok(())
+    }
+}
+
+fn article_href(idx: usize) -> String {
+    format!("articles/article-{}.xhtml", idx + 1)
+}
+
+fn article(entry: &FeedEntry, images: &HashMap<&str, String>) -> String {
+    let body = html::to_xhtml(&entry.content, |url| images.get(url).cloned());
+    let byline = entry
+        .author
+        .iter()
+        .chain(&entry.published)
+        .map(|part| html::escape(part))
+        .collect::<Vec<_>>()
+        .join(" · ");
+    format!(
+        "{}<html xmlns=\"{}\"><head><title>{}</title></head>\
+         <body><h1>{}</h1><p class=\"byline\">{}</p><div class=\"content\">{}</div></body></html>",
+        XML_DECLARATION,
+        XHTML_NAMESPACE,
+        html::escape(&entry.title),
+        html::escape(&entry.title),
+        byline,
+        body
+    )
+}
+
+fn extension(media_type: &str) -> &str {
+    match media_type {
+        "image/png" => "png",
+        "image/jpeg" => "jpg",
+        "image/gif" => "gif",
+        "image/svg+xml" => "svg",
+        "image/webp" => "webp",
+        _ => "bin",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::archive::{EntryMetadata, Timestamp};
+    use crate::Epub;
+
+    #[test]
+    fn writes_archive_metadata() {
+        let feed = Feed {
+            title: "Daily".to_owned(),
+            language: "en".to_owned(),
+            identifier: "urn:daily:2024-03-01".to_owned(),
+            entries: vec![FeedEntry {
+                title: "Morning".to_owned(),
+                content: "<p>News</p>".to_owned(),
+                ..FeedEntry::default()
+            }],
+        };
+        let modified = Timestamp {
+            year: 2024,
+            month: 3,
+            day: 1,
+            hour: 6,
+            minute: 0,
+            second: 0,
+        };
+        let mut metadata = ArchiveMetadata {
+            comment: b"fetched from https://example.com/feed".to_vec(),
+            ..ArchiveMetadata::default()
+        };
+        metadata.entries.insert(
+            "OEBPS/articles/article-1.xhtml".to_owned(),
+            EntryMetadata {
+                modified,
+                comment: "source: https://example.com/morning".to_owned(),
+            },
+        );
+
+        let mut output = Cursor::new(vec![]);
+        feed.write_epub_with(&mut output, &metadata).unwrap();
+        let mut book = Epub::new(Cursor::new(output.into_inner())).unwrap();
+        let written = book.archive_metadata().unwrap();
+        assert_eq!(written.comment, metadata.comment);
+        assert_eq!(
+            written.entries["OEBPS/articles/article-1.xhtml"],
+            metadata.entries["OEBPS/articles/article-1.xhtml"]
+        );
+    }
+
+    #[test]
+    fn converts_html_content() {
+        let feed = Feed {
+            title: "Daily".to_owned(),
+            language: "en".to_owned(),
+            identifier: "urn:daily:2024-03-01".to_owned(),
+            entries: vec![FeedEntry {
+                title: "Morning".to_owned(),
+                content: "<p>It&rsquo;s <b>here<p><img src=https://example.com/a.png>\
+                          <script>track()</script><a href=\"javascript:void(0)\">more</a>"
+                    .to_owned(),
+                images: vec![FeedImage {
+                    url: "https://example.com/a.png".to_owned(),
+                    media_type: "image/png".to_owned(),
+                    data: b"png".to_vec(),
+                }],
+                ..FeedEntry::default()
+            }],
+        };
+
+        let mut output = Cursor::new(vec![]);
+        feed.write_epub(&mut output).unwrap();
+        let mut book = Epub::new(Cursor::new(output.into_inner())).unwrap();
+        let article = book
+            .read(Href::<media_type::XHtml>::try_new("articles/article-1.xhtml").unwrap())
+            .unwrap();
+        let article = std::str::from_utf8(article.as_bytes()).unwrap().to_owned();
+        assert!(article.contains(
+            "<div class=\"content\"><p>It\u{2019}s <b>here</b></p>\
+             <p><img src=\"../images/article-1-1.png\" alt=\"\"/><a>more</a></p></div>"
+        ));
+        let image = book
+            .read(Href::<media_type::Png>::try_new("images/article-1-1.png").unwrap())
+            .unwrap();
+        assert_eq!(image.as_bytes(), b"png");
+    }
+}
@@ -0,0 +1,182 @@
+use std::fmt;
+
+use anyhow::Result;
+
+use crate::storage::Storage;
+use crate::{text, Content, Epub};
+
+const SHINGLE_SIZE: usize = 3;
+
+/// Locality-sensitive hash of the text of a book, fingerprints of books with similar text
+/// differ in few bits regardless of packaging, markup or how the text is split into documents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Fingerprint(pub u64);
+
+impl Fingerprint {
+    /// Number of bits in which the fingerprints differ, 0 for identical text and around 32 for unrelated text.
+    pub fn distance(self, other: Fingerprint) -> u32 {
+        (self.0 ^ other.0).count_ones()
+    }
+
+    /// Whether the fingerprints likely come from the same work, tolerating changes like
+    /// a different edition's front matter or corrected typos.
+    pub fn is_near_duplicate(self, other: Fingerprint) -> bool {
+        self.distance(other) <= 3
+    }
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+impl<S: Storage> Epub<S> {
+    /// Computes a simhash over shingles of the words in the spine documents.
+    /// Words are lowercased and stripped of punctuation before hashing.
+    pub fn fingerprint(&mut self, content: &Content) -> Result<Fingerprint> {
+        let deadline = self.parse_deadline();
+        let mut words = vec![];
+        for document in self.spine_documents(content.spine_items(), deadline) {
+            let text = match document?.doc() {
+                Some(doc) => text::body_text(&doc),
+                None => continue,
+            };
+            words.extend(text.split_whitespace().filter_map(normalize_word));
+        }
+
+        let mut weights = [0i64; 64];
+        for shingle in words.windows(SHINGLE_SIZE.min(words.len().max(1))) {
+            let hash = shingle
+                .iter()
+                .fold(FNV_OFFSET, |hash, word| fnv1a(fnv1a(hash, word.as_bytes()), b" "));
+            let hash = fmix64(hash);
+            for (bit, weight) in weights.iter_mut().enumerate() {
+                if hash & (1 << bit) != 0 {
+                    *weight += 1;
+                } else {
+                    *weight -= 1;
+                }
+            }
+        }
+        let hash = weights
+            .iter()
+            .enumerate()
+            .filter(|(_, weight)| **weight > 0)
+            .fold(0, |hash, (bit, _)| hash | 1 << bit);
+        Ok(Fingerprint(hash))
+    }
+}
+
+fn normalize_word(word: &str) -> Option<String> {
+    let word: String = word
+        .chars()
+        .filter(|char| char.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect();
+    Some(word).filter(|word| !word.is_empty())
+}
+
+pub(crate) const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// FNV-1a, used instead of the std hasher so fingerprints stay comparable across Rust versions.
+pub(crate) fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .fold(hash, |hash, byte| (hash ^ *byte as u64).wrapping_mul(FNV_PRIME))
+}
+
+/// Finalizer of MurmurHash3, spreads the poorly mixed high bits of FNV-1a so that every bit
+/// of the simhash gets an even vote.
+fn fmix64(mut hash: u64) -> u64 {
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ceb9fe1a85ec53);
+    hash ^ hash >> 33
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    /// Deterministic text of `len` words drawn from a small vocabulary.
+    fn words(seed: u64, len: usize) -> Vec<String> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = fmix64(state.wrapping_add(0x9e3779b97f4a7c15));
+                format!("word{}", state % 500)
+            })
+            .collect()
+    }
+
+    fn fingerprint(chapters: &[&[String]]) -> Fingerprint {
+        let items: String = (0..chapters.len())
+            .map(|idx| {
+                format!(
+                    r#"<item id="c{0}" href="text/c{0}.xhtml" media-type="application/xhtml+xml"/>"#,
+                    idx
+                )
+            })
+            .collect();
+        let ids: Vec<String> = (0..chapters.len()).map(|idx| format!("c{}", idx)).collect();
+        let ids: Vec<&str> = ids.iter().map(String::as_str).collect();
+        let package = testing::package(&items, &ids);
+        let documents: Vec<(String, String)> = chapters
+            .iter()
+            .enumerate()
+            .map(|(idx, words)| {
+                (
+                    format!("text/c{}.xhtml", idx),
+                    testing::xhtml(&format!("<p>{}</p>", words.join(" "))),
+                )
+            })
+            .collect();
+        let mut files = vec![("content.opf", package.as_str())];
+        files.extend(
+            documents
+                .iter()
+                .map(|(name, document)| (name.as_str(), document.as_str())),
+        );
+        let mut book = testing::book(&files);
+        let resource = book.package().unwrap();
+        let content = resource.content().unwrap();
+        book.fingerprint(&content).unwrap()
+    }
+
+    #[test]
+    fn ignores_packaging() {
+        let text = words(1, 2000);
+        let whole = fingerprint(&[&text]);
+        let (first, second) = text.split_at(700);
+        let marked: Vec<String> = second
+            .iter()
+            .enumerate()
+            .map(|(idx, word)| match idx % 3 {
+                0 => format!("<em>{}</em>", word.to_uppercase()),
+                1 => format!("{},", word),
+                _ => word.clone(),
+            })
+            .collect();
+        assert_eq!(whole.distance(fingerprint(&[first, &marked])), 0);
+    }
+
+    #[test]
+    fn small_edits_stay_near_and_unrelated_text_is_far() {
+        let text = words(1, 5000);
+        let fingerprint_of = |text: &[String]| fingerprint(&[text]);
+        let original = fingerprint_of(&text);
+
+        let mut edited = text.clone();
+        edited[2500] = "typo".to_owned();
+        edited.remove(4000);
+        assert!(original.distance(fingerprint_of(&edited)) <= 3);
+
+        let unrelated = fingerprint_of(&words(2, 5000));
+        assert!(original.distance(unrelated) >= 16);
+        assert!(!original.is_near_duplicate(unrelated));
+    }
+}
@@ -0,0 +1,465 @@
+//! Conversion of HTML found in the wild, like the content of feeds, into XHTML fit for a book.
+
+/// Elements kept by the conversion, with the attributes kept on them.
+const ALLOWED: &[(&str, &[&str])] = &[
+    ("a", &["href", "title"]),
+    ("img", &["src", "alt", "title", "width", "height"]),
+    ("abbr", &["title"]),
+    ("b", &[]),
+    ("blockquote", &[]),
+    ("br", &[]),
+    ("cite", &[]),
+    ("code", &[]),
+    ("dd", &[]),
+    ("div", &[]),
+    ("dl", &[]),
+    ("dt", &[]),
+    ("em", &[]),
+    ("figcaption", &[]),
+    ("figure", &[]),
+    ("h1", &[]),
+    ("h2", &[]),
+    ("h3", &[]),
+    ("h4", &[]),
+    ("h5", &[]),
+    ("h6", &[]),
+    ("hr", &[]),
+    ("i", &[]),
+    ("li", &[]),
+    ("mark", &[]),
+    ("ol", &[]),
+    ("p", &[]),
+    ("pre", &[]),
+    ("q", &[]),
+    ("s", &[]),
+    ("small", &[]),
+    ("span", &[]),
+    ("strong", &[]),
+    ("sub", &[]),
+    ("sup", &[]),
+    ("table", &[]),
+    ("tbody", &[]),
+    ("td", &["colspan", "rowspan"]),
+    ("th", &["colspan", "rowspan"]),
+    ("thead", &[]),
+    ("tr", &[]),
+    ("u", &[]),
+    ("ul", &[]),
+];
+
+/// Elements which can't have content.
+const VOID: &[&str] = &["br", "hr", "img"];
+
+/// Elements dropped together with their content.
+const SKIPPED: &[&str] = &["head", "iframe", "noscript", "object", "script", "style", "template"];
+
+/// Elements which can't appear inside a paragraph, starting one closes an open paragraph like HTML parsers do.
+const BLOCKS: &[&str] = &[
+    "blockquote",
+    "div",
+    "dl",
+    "figure",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "hr",
+    "ol",
+    "p",
+    "pre",
+    "table",
+    "ul",
+];
+
+/// Converts an HTML fragment into well-formed XHTML. Text, links, images and basic formatting are kept,
+/// scripts, styles and their content are dropped and any other markup is unwrapped, leaving its text.
+/// Named and numeric character references are replaced by the characters they stand for.
+/// Unclosed elements are closed and stray end tags ignored. Image sources are passed through `image`,
+/// which can point them at copies embedded in the book.
+pub(crate) fn to_xhtml(html: &str, image: impl Fn(&str) -> Option<String>) -> String {
+    let mut xhtml = String::with_capacity(html.len());
+    let mut open: Vec<&'static str> = vec![];
+
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        xhtml.push_str(&escape(&decode(&rest[..start])));
+        rest = &rest[start..];
+
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment.find("-->").map(|end| &comment[end + 3..]).unwrap_or_default();
+            continue;
+        }
+        if rest.starts_with("<!") || rest.starts_with("<?") {
+            rest = rest.find('>').map(|end| &rest[end + 1..]).unwrap_or_default();
+            continue;
+        }
+        let closing = rest.starts_with("</");
+        let name_start = if closing { 2 } else { 1 };
+        let name_len = rest[name_start..]
+            .find(|char: char| !char.is_ascii_alphanumeric())
+            .unwrap_or(rest.len() - name_start);
+        if name_len == 0 {
+            xhtml.push_str("&lt;");
+            rest = &rest[1..];
+            continue;
+        }
+        let end = match tag_end(rest) {
+            Some(end) => end,
+            None => {
+                xhtml.push_str("&lt;");
+                rest = &rest[1..];
+                continue;
+            }
+        };
+        let name = rest[name_start..name_start + name_len].to_ascii_lowercase();
+        let attributes = &rest[name_start + name_len..end];
+        rest = rest.get(end + 1..).unwrap_or_default();
+
+        if closing {
+            close(&mut xhtml, &mut open, &name);
+            continue;
+        }
+        if SKIPPED.contains(&name.as_str()) {
+            let end_tag = format!("</{}", name);
+            rest = match rest.to_ascii_lowercase().find(&end_tag) {
+                Some(idx) => rest[idx..]
+                    .find('>')
+                    .map(|end| &rest[idx + end + 1..])
+                    .unwrap_or_default(),
+                None => "",
+            };
+            continue;
+        }
+        let (name, allowed) = match ALLOWED.iter().find(|(allowed, _)| *allowed == name) {
+            Some(&(name, allowed)) => (name, allowed),
+            None => continue,
+        };
+
+        if BLOCKS.contains(&name) && open.contains(&"p") {
+            close(&mut xhtml, &mut open, "p");
+        }
+        let (items, containers): (&[&str], &[&str]) = match name {
+            "li" => (&["li"], &["ul", "ol"]),
+            "dt" | "dd" => (&["dt", "dd"], &["dl"]),
+            "tr" => (&["tr"], &["table", "thead", "tbody"]),
+            "td" | "th" => (&["td", "th"], &["tr", "table"]),
+            _ => (&[], &[]),
+        };
+        let sibling = open
+            .iter()
+            .rev()
+            .find(|open| items.contains(open) || containers.contains(open))
+            .filter(|open| items.contains(open))
+            .copied();
+        if let Some(sibling) = sibling {
+            close(&mut xhtml, &mut open, sibling);
+        }
+
+        xhtml.push('<');
+        xhtml.push_str(name);
+        let mut has_alt = false;
+        for (key, value) in parse_attributes(attributes) {
+            if !allowed.contains(&key.as_str()) {
+                continue;
+            }
+            let value = match (name, key.as_str()) {
+                ("a", "href") if !is_safe_url(&value) => continue,
+                ("img", "src") => image(&value).unwrap_or(value),
+                _ => value,
+            };
+            has_alt |= key == "alt";
+            xhtml.push_str(&format!(" {}=\"{}\"", key, escape(&value)));
+        }
+        if name == "img" && !has_alt {
+            xhtml.push_str(" alt=\"\"");
+        }
+        if VOID.contains(&name) {
+            xhtml.push_str("/>");
+        } else if attributes.trim_end().ends_with('/') {
+            xhtml.push_str(&format!("></{}>", name));
+        } else {
+            xhtml.push('>');
+            open.push(name);
+        }
+    }
+    xhtml.push_str(&escape(&decode(rest)));
+
+    while let Some(name) = open.pop() {
+        xhtml.push_str(&format!("</{}>", name));
+    }
+    xhtml
+}
+
+/// Closes the innermost open element with the given name together with all elements opened after it.
+fn close(xhtml: &mut String, open: &mut Vec<&'static str>, name: &str) {
+    if let Some(idx) = open.iter().rposition(|open| *open == name) {
+        for name in open.drain(idx..).rev() {
+            xhtml.push_str(&format!("</{}>", name));
+        }
+    }
+}
+
+/// Finds the closing bracket of a tag, ignoring brackets inside quoted attribute values.
+fn tag_end(html: &str) -> Option<usize> {
+    let mut quote = None;
+    for (idx, char) in html.char_indices() {
+        match (quote, char) {
+            (None, '"' | '\'') => quote = Some(char),
+            (Some(open), _) if char == open => quote = None,
+            (None, '>') => return Some(idx),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Splits the attribute part of a tag into lowercased names and decoded values,
+/// accepting unquoted values and attributes without a value.
+fn parse_attributes(mut attributes: &str) -> Vec<(String, String)> {
+    let mut parsed = vec![];
+    loop {
+        attributes = attributes.trim_start_matches(|char: char| char.is_whitespace() || char == '/');
+        if attributes.is_empty() {
+            return parsed;
+        }
+        let name_end = attributes
+            .find(|char: char| char.is_whitespace() || char == '=' || char == '/')
+            .unwrap_or(attributes.len());
+        let name = attributes[..name_end].to_ascii_lowercase();
+        attributes = attributes[name_end..].trim_start();
+
+        let value = match attributes.strip_prefix('=') {
+            Some(value) => {
+                let value = value.trim_start();
+                match value.chars().next() {
+                    Some(quote @ ('"' | '\'')) => {
+                        let end = value[1..].find(quote).map(|end| end + 1).unwrap_or(value.len());
+                        attributes = value.get(end + 1..).unwrap_or_default();
+                        &value[1..end]
+                    }
+                    _ => {
+                        let end = value.find(char::is_whitespace).unwrap_or(value.len());
+                        attributes = &value[end..];
+                        &value[..end]
+                    }
+                }
+            }
+            None => "",
+        };
+        parsed.push((name, decode(value)));
+    }
+}
+
+/// Rejects links which would run code when followed.
+fn is_safe_url(url: &str) -> bool {
+    let scheme: String = url
+        .trim_start()
+        .chars()
+        .take_while(|char| *char != ':')
+        .filter(|char| !char.is_whitespace())
+        .collect();
+    !matches!(scheme.to_ascii_lowercase().as_str(), "javascript" | "vbscript" | "data")
+}
+
+/// Replaces character references with the characters they stand for.
+/// References which are not recognized are kept as they are, characters which are not allowed
+/// in XML are dropped, whether they are referenced or appear in the text.
+pub(crate) fn decode(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.extend(rest[..start].chars().filter(|char| is_xml_char(*char)));
+        rest = &rest[start..];
+        let end = rest
+            .char_indices()
+            .skip(1)
+            .take(32)
+            .find(|(_, char)| !(char.is_ascii_alphanumeric() || *char == '#'))
+            .filter(|(_, char)| *char == ';')
+            .map(|(idx, _)| idx);
+        let char = end.and_then(|end| {
+            let reference = &rest[1..end];
+            match reference.strip_prefix('#') {
+                Some(number) => number
+                    .strip_prefix(['x', 'X'])
+                    .map(|hex| u32::from_str_radix(hex, 16))
+                    .unwrap_or_else(|| number.parse())
+                    .ok()
+                    .and_then(char::from_u32),
+                None => entity(reference),
+            }
+        });
+        match (char, end) {
+            (Some(char), Some(end)) => {
+                if is_xml_char(char) {
+                    decoded.push(char);
+                }
+                rest = &rest[end + 1..];
+            }
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.extend(rest.chars().filter(|char| is_xml_char(*char)));
+    decoded
+}
+
+/// Matches the `Char` production of XML 1.0.
+fn is_xml_char(char: char) -> bool {
+    matches!(char, '\t' | '\n' | '\r' | '\u{20}'..='\u{D7FF}' | '\u{E000}'..='\u{FFFD}' | '\u{10000}'..)
+}
+
+/// Names of the characters from U+00A0 to U+00FF, in order.
+const LATIN_1: [&str; 96] = [
+    "nbsp", "iexcl", "cent", "pound", "curren", "yen", "brvbar", "sect", "uml", "copy", "ordf", "laquo", "not", "shy",
+    "reg", "macr", "deg", "plusmn", "sup2", "sup3", "acute", "micro", "para", "middot", "cedil", "sup1", "ordm",
+    "raquo", "frac14", "frac12", "frac34", "iquest", "Agrave", "Aacute", "Acirc", "Atilde", "Auml", "Aring", "AElig",
+    "Ccedil", "Egrave", "Eacute", "Ecirc", "Euml", "Igrave", "Iacute", "Icirc", "Iuml", "ETH", "Ntilde", "Ograve",
+    "Oacute", "Ocirc", "Otilde", "Ouml", "times", "Oslash", "Ugrave", "Uacute", "Ucirc", "Uuml", "Yacute", "THORN",
+    "szlig", "agrave", "aacute", "acirc", "atilde", "auml", "aring", "aelig", "ccedil", "egrave", "eacute", "ecirc",
+    "euml", "igrave", "iacute", "icirc", "iuml", "eth", "ntilde", "ograve", "oacute", "ocirc", "otilde", "ouml",
+    "divide", "oslash", "ugrave", "uacute", "ucirc", "uuml", "yacute", "thorn", "yuml",
+];
+
+/// Looks up a named character reference among the ones common in web content:
+/// the XML ones, Latin-1 and general punctuation.
+fn entity(name: &str) -> Option<char> {
+    if let Some(idx) = LATIN_1.iter().position(|latin| *latin == name) {
+        return char::from_u32(0xA0 + idx as u32);
+    }
+    let char = match name {
+        "quot" => '"',
+        "amp" => '&',
+        "apos" => '\'',
+        "lt" => '<',
+        "gt" => '>',
+        "OElig" => 'Œ',
+        "oelig" => 'œ',
+        "Scaron" => 'Š',
+        "scaron" => 'š',
+        "Yuml" => 'Ÿ',
+        "fnof" => 'ƒ',
+        "circ" => 'ˆ',
+        "tilde" => '˜',
+        "ensp" => '\u{2002}',
+        "emsp" => '\u{2003}',
+        "thinsp" => '\u{2009}',
+        "zwnj" => '\u{200C}',
+        "zwj" => '\u{200D}',
+        "lrm" => '\u{200E}',
+        "rlm" => '\u{200F}',
+        "ndash" => '–',
+        "mdash" => '—',
+        "lsquo" => '‘',
+        "rsquo" => '’',
+        "sbquo" => '‚',
+        "ldquo" => '“',
+        "rdquo" => '”',
+        "bdquo" => '„',
+        "dagger" => '†',
+        "Dagger" => '‡',
+        "bull" => '•',
+        "hellip" => '…',
+        "permil" => '‰',
+        "prime" => '′',
+        "Prime" => '″',
+        "lsaquo" => '‹',
+        "rsaquo" => '›',
+        "oline" => '‾',
+        "euro" => '€',
+        "trade" => '™',
+        "larr" => '←',
+        "uarr" => '↑',
+        "rarr" => '→',
+        "darr" => '↓',
+        "harr" => '↔',
+        "minus" => '−',
+        _ => return None,
+    };
+    Some(char)
+}
+
+pub(crate) fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn convert(html: &str) -> String {
+        let xhtml = to_xhtml(html, |url| {
+            url.strip_prefix("https://example.com/")
+                .map(|name| format!("../images/{}", name))
+        });
+        let wrapped = format!("<div xmlns=\"http://www.w3.org/1999/xhtml\">{}</div>", xhtml);
+        roxmltree::Document::parse(&wrapped).expect("conversion produced malformed XHTML");
+        xhtml
+    }
+
+    #[test]
+    fn decodes_named_entities() {
+        assert_eq!(
+            convert("<p>It&rsquo;s &ldquo;caf&eacute;&rdquo; &amp; more&hellip;&nbsp;&#8212;&#x41;</p>"),
+            "<p>It’s “café” &amp; more…\u{a0}—A</p>"
+        );
+        assert_eq!(convert("AT&T &bogus; a < b"), "AT&amp;T &amp;bogus; a &lt; b");
+        assert_eq!(convert("a&#0;b"), "ab");
+        assert_eq!(convert("a&#1;b"), "ab");
+        assert_eq!(convert("a\u{1}b"), "ab");
+        assert_eq!(convert("a&#xFFFE;b"), "ab");
+        assert_eq!(
+            convert("<img alt=\"a&#1;b\u{1}c\" src=\"https://example.com/a.png\">"),
+            r#"<img alt="abc" src="../images/a.png"/>"#
+        );
+    }
+
+    #[test]
+    fn drops_scripts_and_styles() {
+        assert_eq!(
+            convert(
+                "<p>Hello<script>alert(1)</script></p><STYLE>p { color: red }</STYLE><noscript>enable js</noscript>"
+            ),
+            "<p>Hello</p>"
+        );
+        assert_eq!(convert("<script>document.write('<p>')</script>text"), "text");
+    }
+
+    #[test]
+    fn keeps_images_links_and_formatting() {
+        assert_eq!(
+            convert(
+                r#"<div class="post"><img src="https://example.com/a.png" onload="x()"><p>See <a href="https://example.com/?a=1&amp;b=2" target=_blank>this</a>, <b>bold</b> <font color=red>red</font></p></div>"#
+            ),
+            r#"<div><img src="../images/a.png" alt=""/><p>See <a href="https://example.com/?a=1&amp;b=2">this</a>, <b>bold</b> red</p></div>"#
+        );
+        assert_eq!(
+            convert(r#"<img alt='A "quote"' src=https://cdn.example.org/b.jpg />"#),
+            r#"<img alt="A &quot;quote&quot;" src="https://cdn.example.org/b.jpg"/>"#
+        );
+        assert_eq!(convert(r#"<a href="javascript:alert(1)">x</a>"#), "<a>x</a>");
+    }
+
+    #[test]
+    fn repairs_structure() {
+        assert_eq!(convert("<p>one<p>two<br>three"), "<p>one</p><p>two<br/>three</p>");
+        assert_eq!(convert("<ul><li>a<li>b</ul>"), "<ul><li>a</li><li>b</li></ul>");
+        assert_eq!(
+            convert("<ul><li>a<ul><li>b</ul></ul>"),
+            "<ul><li>a<ul><li>b</li></ul></li></ul>"
+        );
+        assert_eq!(convert("<em>x</strong></em>y</p>"), "<em>x</em>y");
+        assert_eq!(
+            convert("<p>quote<blockquote>text</blockquote>"),
+            "<p>quote</p><blockquote>text</blockquote>"
+        );
+        assert_eq!(convert("<!-- comment --><!DOCTYPE html>a<b"), "a&lt;b");
+    }
+}
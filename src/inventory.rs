@@ -0,0 +1,250 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+
+use crate::storage::{EntryInfo, NotFound, Storage};
+use crate::{links, media_type, path, ContainerHref, Epub, Resource, Utf8String};
+
+/// Report on a single entry of the container.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// Path of the entry within the container.
+    pub name: String,
+    /// Media type declared in the manifest, if the entry is listed there.
+    pub declared_media_type: Option<String>,
+    /// Media type guessed from the contents of the entry.
+    pub sniffed_media_type: Option<&'static str>,
+    /// Encoding of textual entries, taken from the byte order mark or the XML declaration.
+    pub encoding: Option<String>,
+    pub compressed_size: u64,
+    pub size: u64,
+    /// Number of references to the entry from the manifest, documents and stylesheets.
+    pub referenced_by: usize,
+}
+
+impl<S: Storage> Epub<S> {
+    /// Reads every entry of the container and reports on its type, encoding, size and usage.
+    pub fn inventory(&mut self) -> Result<Vec<Entry>> {
+        let deadline = self.parse_deadline();
+
+        let package = self.package.clone();
+        let package = match self.read_container_bytes(&package, deadline) {
+            Ok(bytes) => Utf8String::try_from(bytes).ok().map(Resource::<media_type::Opf>::new),
+            Err(err) if err.is::<NotFound>() => None,
+            Err(err) => return Err(err),
+        };
+        // Entries of a book without a readable package are reported without declared media types.
+        let mut declared = HashMap::new();
+        if let Some(content) = package.as_ref().and_then(|resource| resource.content().ok()) {
+            for item in content.manifest.items {
+                if let Some(path) = path::resolve(self.package.as_ref(), &item.href) {
+                    declared.insert(path, item.media_type.into_owned());
+                }
+            }
+        }
+        let mut references: HashMap<String, usize> = HashMap::new();
+        for path in declared.keys() {
+            *references.entry(path.clone()).or_default() += 1;
+        }
+
+        let mut result = vec![];
+        for EntryInfo {
+            name,
+            size,
+            compressed_size,
+            ..
+        } in self.storage.entries()?
+        {
+            deadline.check()?;
+            let bytes = self.read_container_bytes(&ContainerHref::entry(name.as_str()), deadline)?;
+            let declared_media_type = declared.get(&name).cloned();
+            let sniffed_media_type = sniff(&bytes);
+
+            let media_type = declared_media_type.as_deref().or(sniffed_media_type);
+            let urls = match (media_type, std::str::from_utf8(&bytes)) {
+                (Some("application/xhtml+xml" | "image/svg+xml"), Ok(text)) => match roxmltree::Document::parse(text) {
+                    Ok(doc) => links::document_links(&doc).map(str::to_owned).collect(),
+                    Err(_) => vec![],
+                },
+                (Some("text/css"), Ok(text)) => links::css_links(text).into_iter().map(str::to_owned).collect(),
+                _ => vec![],
+            };
+            for url in urls {
+                if let Some(target) = path::resolve(&name, &url) {
+                    *references.entry(target).or_default() += 1;
+                }
+            }
+
+            result.push(Entry {
+                encoding: encoding(&bytes, media_type),
+                declared_media_type,
+                sniffed_media_type,
+                compressed_size,
+                size,
+                referenced_by: 0,
+                name,
+            });
+        }
+
+        for entry in &mut result {
+            entry.referenced_by = references.get(&entry.name).copied().unwrap_or_default();
+        }
+        Ok(result)
+    }
+}
+
+fn sniff(bytes: &[u8]) -> Option<&'static str> {
+    let media_type = match bytes {
+        [0x89, b'P', b'N', b'G', ..] => "image/png",
+        [0xFF, 0xD8, 0xFF, ..] => "image/jpeg",
+        [b'G', b'I', b'F', b'8', ..] => "image/gif",
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => "image/webp",
+        [b'O', b'T', b'T', b'O', ..] => "font/otf",
+        [0x00, 0x01, 0x00, 0x00, ..] | [b't', b'r', b'u', b'e', ..] => "font/ttf",
+        [b'w', b'O', b'F', b'F', ..] => "font/woff",
+        [b'w', b'O', b'F', b'2', ..] => "font/woff2",
+        [b'I', b'D', b'3', ..] | [0xFF, 0xFB | 0xF3 | 0xF2, ..] => "audio/mpeg",
+        [b'O', b'g', b'g', b'S', ..] => "audio/ogg",
+        [_, _, _, _, b'f', b't', b'y', b'p', ..] => "video/mp4",
+        [b'%', b'P', b'D', b'F', ..] => "application/pdf",
+        [b'P', b'K', 0x03, 0x04, ..] => "application/zip",
+        _ => return sniff_text(bytes),
+    };
+    Some(media_type)
+}
+
+fn sniff_text(bytes: &[u8]) -> Option<&'static str> {
+    let text = std::str::from_utf8(bytes).ok()?.trim_start_matches('\u{feff}');
+    let head = &text[..text.char_indices().nth(1024).map(|(idx, _)| idx).unwrap_or(text.len())];
+    let media_type = if head.contains("<html") {
+        "application/xhtml+xml"
+    } else if head.contains("<svg") {
+        "image/svg+xml"
+    } else if head.contains("<package") {
+        "application/oebps-package+xml"
+    } else if head.contains("<ncx") {
+        "application/x-dtbncx+xml"
+    } else if head.trim_start().starts_with('<') {
+        "application/xml"
+    } else {
+        "text/plain"
+    };
+    Some(media_type)
+}
+
+fn encoding(bytes: &[u8], media_type: Option<&str>) -> Option<String> {
+    let encoding = match bytes {
+        [0xEF, 0xBB, 0xBF, ..] => "utf-8",
+        [0xFF, 0xFE, ..] => "utf-16le",
+        [0xFE, 0xFF, ..] => "utf-16be",
+        _ if !is_textual(media_type?) => return None,
+        _ => match declared_encoding(bytes) {
+            Some(declared) => return Some(declared),
+            None if std::str::from_utf8(bytes).is_ok() => "utf-8",
+            None => "unknown",
+        },
+    };
+    Some(encoding.to_owned())
+}
+
+fn is_textual(media_type: &str) -> bool {
+    media_type.starts_with("text/") || media_type.ends_with("+xml") || media_type == "application/xml"
+}
+
+/// Reads the encoding from an XML declaration or a CSS `@charset` rule.
+fn declared_encoding(bytes: &[u8]) -> Option<String> {
+    let head = String::from_utf8_lossy(&bytes[..bytes.len().min(256)]);
+    let rest = if head.starts_with("<?xml") {
+        let decl = &head[..head.find("?>")?];
+        decl[decl.find("encoding")? + 8..]
+            .trim_start()
+            .strip_prefix('=')?
+            .trim_start()
+    } else {
+        head.strip_prefix("@charset")?.trim_start()
+    };
+    let quote = rest.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+    let value = &rest[1..];
+    Some(value[..value.find(quote)?].to_ascii_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::testing;
+    use crate::timeout::Timeout;
+
+    #[test]
+    fn reports_types_encodings_and_references() {
+        let package = testing::package(
+            r#"<item id="one" href="one.xhtml" media-type="application/xhtml+xml"/>
+               <item id="latin" href="latin.xhtml" media-type="application/xhtml+xml"/>
+               <item id="css" href="style.css" media-type="text/css"/>
+               <item id="cover" href="cover.png" media-type="image/png"/>"#,
+            &["one", "latin"],
+        );
+        let one = r#"<html xmlns="http://www.w3.org/1999/xhtml"><head><title>One</title>
+            <link rel="stylesheet" href="style.css"/></head>
+            <body><img src="cover.png"/><a href="latin.xhtml">Next</a></body></html>"#;
+        let latin = r#"<?xml version="1.0" encoding="ISO-8859-1"?><html><body><p>Latin</p></body></html>"#;
+        let mut book = testing::book(&[
+            ("content.opf", &package),
+            ("one.xhtml", one),
+            ("latin.xhtml", latin),
+            ("style.css", "body { background: url(cover.png) }"),
+        ]);
+        book.storage.insert("OEBPS/cover.png", b"\x89PNG\r\n\x1a\n".to_vec());
+        book.storage.insert("OEBPS/extra.jpg", b"\xFF\xD8\xFF\xE0".to_vec());
+        book.storage.insert("OEBPS/notes.txt", b"\xEF\xBB\xBFnotes".to_vec());
+
+        let inventory = book.inventory().unwrap();
+        let entry = |name: &str| inventory.iter().find(|entry| entry.name == name).unwrap();
+        let summary = |name: &str| {
+            let entry = entry(name);
+            (
+                entry.declared_media_type.as_deref(),
+                entry.sniffed_media_type,
+                entry.encoding.as_deref(),
+                entry.referenced_by,
+            )
+        };
+        assert_eq!(
+            summary("OEBPS/one.xhtml"),
+            (
+                Some("application/xhtml+xml"),
+                Some("application/xhtml+xml"),
+                Some("utf-8"),
+                1
+            )
+        );
+        assert_eq!(
+            summary("OEBPS/latin.xhtml"),
+            (
+                Some("application/xhtml+xml"),
+                Some("application/xhtml+xml"),
+                Some("iso-8859-1"),
+                2
+            )
+        );
+        assert_eq!(
+            summary("OEBPS/style.css"),
+            (Some("text/css"), Some("text/plain"), Some("utf-8"), 2)
+        );
+        assert_eq!(
+            summary("OEBPS/cover.png"),
+            (Some("image/png"), Some("image/png"), None, 3)
+        );
+        assert_eq!(summary("OEBPS/extra.jpg"), (None, Some("image/jpeg"), None, 0));
+        assert_eq!(summary("OEBPS/notes.txt"), (None, Some("text/plain"), Some("utf-8"), 0));
+        assert_eq!(entry("OEBPS/cover.png").size, 8);
+
+        book.storage.insert("OEBPS/content.opf", b"<package>".to_vec());
+        let inventory = book.inventory().unwrap();
+        assert_eq!(inventory.len(), 8);
+        assert!(inventory.iter().all(|entry| entry.declared_media_type.is_none()));
+
+        book.timeouts.parse = Some(Duration::ZERO);
+        assert!(book.inventory().unwrap_err().is::<Timeout>());
+    }
+}
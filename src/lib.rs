@@ -5,19 +5,139 @@ use std::marker::PhantomData;
 use std::string::FromUtf8Error;
 
 use anyhow::Result;
+pub use container::{ContainerHref, PackageHref};
+pub use nav::{NavDoc, NavEntry};
+pub use package_info::PackageInfo;
+use storage::{DirStorage, DuplicatePolicy, Storage, ZipStorage};
 use strong_xml::{XmlRead, XmlWrite};
+use timeout::{Deadline, Timeouts};
 pub use {roxmltree, strong_xml};
 
+pub mod anchor;
+pub mod archive;
+pub mod changelog;
+pub mod chapter;
+mod container;
+#[cfg(feature = "fonts")]
+pub mod coverage;
+pub mod diagnostics;
+mod extract;
+#[cfg(feature = "feed")]
+pub mod feed;
+pub mod fingerprint;
+#[cfg(feature = "feed")]
+mod html;
+pub mod inventory;
+mod links;
+mod nav;
+mod outline;
+mod package_info;
+mod path;
+pub mod revalidate;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod storage;
+#[cfg(test)]
+mod testing;
+mod text;
+pub mod timeout;
+
+/// Directory of the package document in books written by this crate.
+const PACKAGE_DIR: &str = "OEBPS/";
+
 #[derive(Debug)]
-pub struct Epub<R> {
-    archive: zip::ZipArchive<R>,
+pub struct Epub<S> {
+    storage: S,
+    timeouts: Timeouts,
+    /// Location of the package document, all hrefs in the book are relative to it.
+    package: ContainerHref<'static>,
 }
 
-impl<R: Read + Seek> Epub<R> {
+impl<R: Read + Seek> Epub<ZipStorage<R>> {
     pub fn new(input: R) -> Result<Self> {
-        let archive = zip::ZipArchive::new(input)?;
-        let result = Self { archive };
-        Ok(result)
+        Self::with_timeouts(input, Timeouts::default())
+    }
+
+    /// Opens a book enforcing time budgets on the operations performed on it,
+    /// exceeding one fails with a [`timeout::Timeout`] error.
+    pub fn with_timeouts(input: R, timeouts: Timeouts) -> Result<Self> {
+        Self::open_with(input, timeouts, DuplicatePolicy::default())
+    }
+
+    /// Like [`Epub::with_timeouts`], also choosing which file to use when the archive
+    /// contains several with the same name. Such files are reported by [`Epub::validate`].
+    pub fn open_with(input: R, timeouts: Timeouts, duplicates: DuplicatePolicy) -> Result<Self> {
+        let deadline = Deadline::after(timeouts.open, timeout::Operation::Open);
+        let storage = ZipStorage::with_deadline(input, deadline, duplicates)?;
+        Self::from_storage(storage, timeouts)
+    }
+
+    /// Returns the comment of the archive and the timestamps and comments of its files.
+    pub fn archive_metadata(&mut self) -> Result<archive::ArchiveMetadata> {
+        self.storage.metadata()
+    }
+}
+
+impl Epub<DirStorage> {
+    /// Opens a book unpacked into a directory, as used when authoring.
+    /// The directory has to contain `META-INF/container.xml` like a packaged book would.
+    pub fn open_dir(root: impl AsRef<std::path::Path>) -> Result<Self> {
+        Self::open_dir_with(root, Timeouts::default())
+    }
+
+    /// Like [`Epub::open_dir`], enforcing time budgets on the operations performed on the book.
+    pub fn open_dir_with(root: impl AsRef<std::path::Path>, timeouts: Timeouts) -> Result<Self> {
+        let storage = DirStorage::new(root.as_ref());
+        if !root.as_ref().join("META-INF").join("container.xml").is_file() {
+            anyhow::bail!("{} is missing META-INF/container.xml", root.as_ref().display());
+        }
+        Self::from_storage(storage, timeouts)
+    }
+}
+
+impl<S: Storage> Epub<S> {
+    /// Opens a book stored in `storage`, locating the package document through `META-INF/container.xml`.
+    /// Storage without the container file is assumed to keep the package at [`ContainerHref::DEFAULT_PACKAGE`].
+    pub fn from_storage(storage: S, timeouts: Timeouts) -> Result<Self> {
+        let mut book = Self {
+            storage,
+            timeouts,
+            package: ContainerHref::DEFAULT_PACKAGE,
+        };
+        match book.read_container_bytes(&ContainerHref::CONTAINER, Deadline::NONE) {
+            Ok(container) => book.package = ContainerHref::rootfile(&container)?,
+            Err(err) if err.is::<storage::NotFound>() => {}
+            Err(err) => return Err(err),
+        }
+        Ok(book)
+    }
+
+    /// Returns the location of the package document within the container.
+    pub fn package_href(&self) -> &ContainerHref<'static> {
+        &self.package
+    }
+
+    /// Resolves an href relative to the package document to a path within the container.
+    pub fn resolve<Media>(&self, href: &Href<Media>) -> Option<ContainerHref<'static>> {
+        self.package.join(href)
+    }
+
+    /// Reads the package document declared by the container.
+    pub fn package(&mut self) -> Result<Resource<media_type::Opf>> {
+        self.package_within(Deadline::NONE)
+    }
+
+    pub(crate) fn package_within(&mut self, deadline: Deadline) -> Result<Resource<media_type::Opf>> {
+        let bytes = self.read_container_bytes(&self.package.clone(), deadline)?;
+        Ok(Resource::new(bytes.try_into()?))
+    }
+
+    pub fn storage(&self) -> &S {
+        &self.storage
+    }
+
+    pub fn into_storage(self) -> S {
+        self.storage
     }
 
     pub fn read<Media>(&mut self, href: Href<'_, Media>) -> Result<Resource<Media>>
@@ -26,18 +146,62 @@ impl<R: Read + Seek> Epub<R> {
         Media::Value: TryFrom<Vec<u8>>,
         <<Media as media_type::MediaType>::Value as TryFrom<Vec<u8>>>::Error: std::error::Error + Send + Sync + 'static,
     {
-        let path = "OEBPS/".to_owned() + href.url.as_ref();
-        let mut entry = self.archive.by_name(&path)?;
-        let mut bytes = Vec::with_capacity(entry.size() as usize);
-        entry.read_to_end(&mut bytes)?;
+        self.read_within(href, Deadline::NONE)
+    }
 
+    /// Like [`Epub::read`], also giving up once the deadline of the operation the read is part of passes.
+    pub(crate) fn read_within<Media>(&mut self, href: Href<'_, Media>, deadline: Deadline) -> Result<Resource<Media>>
+    where
+        Media: media_type::MediaType,
+        Media::Value: TryFrom<Vec<u8>>,
+        <<Media as media_type::MediaType>::Value as TryFrom<Vec<u8>>>::Error: std::error::Error + Send + Sync + 'static,
+    {
+        let bytes = self.read_bytes(&href, deadline)?;
         Ok(Resource::new(bytes.try_into()?))
     }
+
+    pub(crate) fn read_bytes<Media>(&mut self, href: &Href<'_, Media>, deadline: Deadline) -> Result<Vec<u8>> {
+        let path = self.resolve(href).ok_or_else(|| storage::NotFound {
+            name: href.as_ref().to_owned(),
+        })?;
+        self.read_container_bytes(&path, deadline)
+    }
+
+    /// Reads a file by its path in the container, within the read budget and the deadline
+    /// of the operation the read is part of, [`Deadline::NONE`] for reads on their own.
+    pub(crate) fn read_container_bytes(&mut self, path: &ContainerHref, deadline: Deadline) -> Result<Vec<u8>> {
+        let deadline = Deadline::after(self.timeouts.read, timeout::Operation::Read).earliest(deadline);
+        let mut entry = self.storage.open(path.as_ref())?;
+        let mut bytes = vec![];
+        let mut chunk = [0; 64 * 1024];
+        loop {
+            deadline.check()?;
+            match entry.read(&mut chunk)? {
+                0 => break,
+                len => bytes.extend_from_slice(&chunk[..len]),
+            }
+        }
+        Ok(bytes)
+    }
+
+    pub(crate) fn parse_deadline(&self) -> Deadline {
+        Deadline::after(self.timeouts.parse, timeout::Operation::Parse)
+    }
+
+    pub(crate) fn validate_deadline(&self) -> Deadline {
+        Deadline::after(self.timeouts.validate, timeout::Operation::Validate)
+    }
 }
 
-#[derive(Debug, PartialEq, XmlWrite, XmlRead)]
+#[derive(Debug, Clone, PartialEq, XmlWrite, XmlRead)]
 #[xml(tag = "package")]
 pub struct Content<'a> {
+    #[xml(attr = "xmlns")]
+    pub xmlns: Option<Cow<'a, str>>,
+    #[xml(attr = "version")]
+    pub version: Option<Cow<'a, str>>,
+    #[xml(attr = "unique-identifier")]
+    pub unique_identifier: Option<Cow<'a, str>>,
     #[xml(child = "metadata")]
     pub metadata: Metadata<'a>,
     #[xml(child = "manifest")]
@@ -48,25 +212,76 @@ pub struct Content<'a> {
     pub guide: Guide<'a>,
 }
 
-#[derive(Debug, PartialEq, XmlWrite, XmlRead)]
+impl<'a> Content<'a> {
+    pub const NAMESPACE: &'static str = "http://www.idpf.org/2007/opf";
+    pub const VERSION: &'static str = "2.0";
+
+    /// Returns the href of the EPUB 3 navigation document, if the book has one.
+    pub fn nav_href(&'a self) -> Option<Href<'a, media_type::XHtml>> {
+        self.manifest
+            .items
+            .iter()
+            .find(|item| item.has_property("nav"))
+            .and_then(Item::xhtml_href)
+    }
+
+    /// Returns the href of the NCX table of contents, the item referenced by the `toc` attribute of the spine
+    /// or else the first item with the NCX media type.
+    pub fn ncx_href(&'a self) -> Option<Href<'a, media_type::DtbNcx>> {
+        let items = &self.manifest.items;
+        self.spine
+            .toc
+            .as_ref()
+            .and_then(|id| items.iter().find(|item| item.id == *id))
+            .and_then(Item::ncx_href)
+            .or_else(|| items.iter().find_map(Item::ncx_href))
+    }
+
+    /// Returns the path the srcs of the NCX are relative to. Tables which don't come from the book,
+    /// like synthesized ones, are relative to the package document and get an empty path.
+    pub(crate) fn ncx_base(&'a self) -> String {
+        self.ncx_href().map(Href::into_string).unwrap_or_default()
+    }
+
+    /// Returns the manifest items referenced by the spine, in reading order.
+    pub fn spine_items(&'a self) -> impl Iterator<Item = &'a Item<'a>> {
+        self.spine
+            .refs
+            .iter()
+            .filter_map(|item_ref| self.manifest.items.iter().find(|item| item.id == item_ref.id_ref))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, XmlWrite, XmlRead)]
 #[xml(tag = "metadata")]
 pub struct Metadata<'a> {
+    #[xml(attr = "xmlns:dc")]
+    pub xmlns_dc: Option<Cow<'a, str>>,
+    #[xml(attr = "xmlns:opf")]
+    pub xmlns_opf: Option<Cow<'a, str>>,
     #[xml(flatten_text = "dc:title")]
     pub title: Cow<'a, str>,
     #[xml(flatten_text = "dc:language")]
     pub language: Cow<'a, str>,
     #[xml(flatten_text = "dc:identifier")]
     pub identifier: Cow<'a, str>,
+    /// `meta` records of the NCX head, like `dtb:uid` and `dtb:depth`, each a `name` and `content` pair.
+    #[xml(child = "meta")]
+    pub meta: Vec<Meta<'a>>,
 }
 
-#[derive(Debug, PartialEq, XmlWrite, XmlRead)]
+impl<'a> Metadata<'a> {
+    pub const DC_NAMESPACE: &'static str = "http://purl.org/dc/elements/1.1/";
+}
+
+#[derive(Debug, Clone, PartialEq, XmlWrite, XmlRead)]
 #[xml(tag = "manifest")]
 pub struct Manifest<'a> {
     #[xml(child = "item")]
     pub items: Vec<Item<'a>>,
 }
 
-#[derive(Debug, PartialEq, XmlWrite, XmlRead)]
+#[derive(Debug, Clone, PartialEq, XmlWrite, XmlRead)]
 #[xml(tag = "item")]
 pub struct Item<'a> {
     #[xml(attr = "id")]
@@ -75,35 +290,48 @@ pub struct Item<'a> {
     pub media_type: Cow<'a, str>,
     #[xml(attr = "href")]
     href: Cow<'a, str>,
+    #[xml(attr = "properties")]
+    pub properties: Option<Cow<'a, str>>,
 }
 
 impl<'a> Item<'a> {
+    pub fn has_property(&self, property: &str) -> bool {
+        self.properties
+            .iter()
+            .flat_map(|properties| properties.split_whitespace())
+            .any(|candidate| candidate == property)
+    }
+
     pub fn xhtml_href(&'a self) -> Option<Href<'a, media_type::XHtml>> {
-        self.match_href("application/xhtml+xml")
+        self.match_href()
+    }
+
+    pub fn ncx_href(&'a self) -> Option<Href<'a, media_type::DtbNcx>> {
+        self.match_href()
     }
 
     pub fn css_href(&'a self) -> Option<Href<'a, media_type::Css>> {
-        self.match_href("text/css")
+        self.match_href()
     }
 
     pub fn png_href(&'a self) -> Option<Href<'a, media_type::Png>> {
-        self.match_href("image/png")
+        self.match_href()
     }
 
     pub fn jpeg_href(&'a self) -> Option<Href<'a, media_type::Jpeg>> {
-        self.match_href("image/jpeg")
+        self.match_href()
     }
 
     pub fn gif_href(&'a self) -> Option<Href<'a, media_type::Gif>> {
-        self.match_href("image/gif")
+        self.match_href()
     }
 
     pub fn svg_href(&'a self) -> Option<Href<'a, media_type::Svg>> {
-        self.match_href("image/svg+xml")
+        self.match_href()
     }
 
-    fn match_href<Media>(&'a self, media_type: &str) -> Option<Href<'a, Media>> {
-        if self.media_type.as_ref() == media_type {
+    fn match_href<Media: media_type::MediaType>(&'a self) -> Option<Href<'a, Media>> {
+        if self.media_type.as_ref() == Media::MEDIA_TYPE {
             Some(Href::new(self.href.clone()))
         } else {
             None
@@ -111,28 +339,30 @@ impl<'a> Item<'a> {
     }
 }
 
-#[derive(Debug, PartialEq, XmlWrite, XmlRead)]
+#[derive(Debug, Clone, PartialEq, XmlWrite, XmlRead)]
 #[xml(tag = "spine")]
 pub struct Spine<'a> {
+    #[xml(attr = "toc")]
+    pub toc: Option<Cow<'a, str>>,
     #[xml(child = "itemref")]
     pub refs: Vec<ItemRef<'a>>,
 }
 
-#[derive(Debug, PartialEq, XmlWrite, XmlRead)]
+#[derive(Debug, Clone, PartialEq, XmlWrite, XmlRead)]
 #[xml(tag = "itemref")]
 pub struct ItemRef<'a> {
     #[xml(attr = "idref")]
     pub id_ref: Cow<'a, str>,
 }
 
-#[derive(Debug, PartialEq, XmlWrite, XmlRead)]
+#[derive(Debug, Clone, PartialEq, XmlWrite, XmlRead)]
 #[xml(tag = "guide")]
 pub struct Guide<'a> {
     #[xml(child = "reference")]
     pub references: Vec<Reference<'a>>,
 }
 
-#[derive(Debug, PartialEq, XmlWrite, XmlRead)]
+#[derive(Debug, Clone, PartialEq, XmlWrite, XmlRead)]
 #[xml(tag = "reference")]
 pub struct Reference<'a> {
     #[xml(attr = "type")]
@@ -149,29 +379,83 @@ impl<'a> Reference<'a> {
     }
 }
 
-#[derive(Debug, PartialEq, XmlWrite, XmlRead)]
+#[derive(Debug, Clone, PartialEq, XmlWrite, XmlRead)]
 #[xml(tag = "ncx")]
 pub struct TableOfContents<'a> {
+    #[xml(attr = "xmlns")]
+    pub xmlns: Option<Cow<'a, str>>,
+    #[xml(attr = "version")]
+    pub version: Option<Cow<'a, str>>,
+    #[xml(child = "head")]
+    pub head: Option<Head<'a>>,
     #[xml(child = "navMap")]
     pub map: NavMap<'a>,
 }
 
 impl<'a> TableOfContents<'a> {
+    pub const NAMESPACE: &'static str = "http://www.daisy.org/z3986/2005/ncx/";
+    pub const VERSION: &'static str = "2005-1";
+    pub const SYNTHESIZED: &'static str = "epubs:synthesized";
+
     pub fn points(&'a self) -> &'a [NavPoint<'a>] {
         &self.map.points
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.points.is_empty()
+    }
+
+    /// Returns true if the table was generated from the book's headings rather than read from it.
+    pub fn is_synthesized(&self) -> bool {
+        self.head
+            .iter()
+            .flat_map(|head| &head.meta)
+            .any(|meta| meta.name.as_deref() == Some(Self::SYNTHESIZED))
+    }
+
+    /// Exports the outline as a JSON array of entries with `title`, `href`, `playOrder` and `children` keys.
+    /// Entries without a valid `playOrder` attribute get their position in document order instead.
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut order = 0;
+        self.map
+            .points
+            .iter()
+            .map(|point| point.to_json_from(&mut order))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, XmlWrite, XmlRead)]
+#[xml(tag = "head")]
+pub struct Head<'a> {
+    /// `meta` records of the NCX head, like `dtb:uid` and `dtb:depth`, each a `name` and `content` pair.
+    #[xml(child = "meta")]
+    pub meta: Vec<Meta<'a>>,
+}
+
+#[derive(Debug, Clone, PartialEq, XmlWrite, XmlRead)]
+#[xml(tag = "meta")]
+pub struct Meta<'a> {
+    #[xml(attr = "name")]
+    pub name: Option<Cow<'a, str>>,
+    #[xml(attr = "content")]
+    pub content: Option<Cow<'a, str>>,
 }
 
-#[derive(Debug, PartialEq, XmlWrite, XmlRead)]
+#[derive(Debug, Clone, PartialEq, XmlWrite, XmlRead)]
 #[xml(tag = "navMap")]
 pub struct NavMap<'a> {
     #[xml(child = "navPoint")]
     pub points: Vec<NavPoint<'a>>,
 }
 
-#[derive(Debug, PartialEq, XmlWrite, XmlRead)]
+#[derive(Debug, Clone, PartialEq, XmlWrite, XmlRead)]
 #[xml(tag = "navPoint")]
 pub struct NavPoint<'a> {
+    #[xml(attr = "id")]
+    pub id: Option<Cow<'a, str>>,
+    #[xml(attr = "playOrder")]
+    pub play_order: Option<Cow<'a, str>>,
     #[xml(child = "navLabel")]
     pub label: NavLabel<'a>,
     #[xml(child = "content")]
@@ -184,16 +468,35 @@ impl<'a> NavPoint<'a> {
     pub fn href(&'a self) -> Href<'a, media_type::XHtml> {
         Href::new(self.content.src.clone())
     }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        self.to_json_from(&mut 0)
+    }
+
+    fn to_json_from(&self, order: &mut u32) -> serde_json::Value {
+        *order += 1;
+        let play_order = self
+            .play_order
+            .as_ref()
+            .and_then(|play_order| play_order.trim().parse::<u32>().ok())
+            .unwrap_or(*order);
+        serde_json::json!({
+            "title": text::collapse_whitespace(&self.label.text),
+            "href": self.content.src,
+            "playOrder": play_order,
+            "children": self.children.iter().map(|child| child.to_json_from(order)).collect::<Vec<_>>(),
+        })
+    }
 }
 
-#[derive(Debug, PartialEq, XmlWrite, XmlRead)]
+#[derive(Debug, Clone, PartialEq, XmlWrite, XmlRead)]
 #[xml(tag = "navLabel")]
 pub struct NavLabel<'a> {
     #[xml(flatten_text = "text")]
     pub text: Cow<'a, str>,
 }
 
-#[derive(Debug, PartialEq, XmlWrite, XmlRead)]
+#[derive(Debug, Clone, PartialEq, XmlWrite, XmlRead)]
 #[xml(tag = "content")]
 pub struct NavContent<'a> {
     #[xml(attr = "src")]
@@ -212,36 +515,94 @@ impl<Media: media_type::MediaType> Resource<Media> {
             phantom: PhantomData,
         }
     }
+
+    pub fn into_data(self) -> Media::Value {
+        self.data
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        self.data.as_ref()
+    }
+
+    pub fn len(&self) -> usize {
+        self.as_bytes().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.as_bytes().is_empty()
+    }
+
+    /// Transforms the data of the resource, possibly changing its media type.
+    pub fn map<Other: media_type::MediaType>(self, f: impl FnOnce(Media::Value) -> Other::Value) -> Resource<Other> {
+        Resource::new(f(self.data))
+    }
+}
+
+impl<Media: media_type::MediaType<Value = Utf8String>> Resource<Media> {
+    pub fn as_str(&self) -> &str {
+        &self.data.0
+    }
+}
+
+impl<Media: media_type::MediaType> AsRef<[u8]> for Resource<Media> {
+    fn as_ref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+impl<Media: media_type::MediaType> From<Resource<Media>> for Vec<u8> {
+    fn from(resource: Resource<Media>) -> Self {
+        resource.data.into()
+    }
+}
+
+impl<Media: media_type::MediaType<Value = Utf8String>> From<Resource<Media>> for String {
+    fn from(resource: Resource<Media>) -> Self {
+        resource.data.0
+    }
 }
 
 impl<'a> Resource<media_type::XHtml> {
-    pub fn doc(&'a self) -> Result<roxmltree::Document> {
+    pub fn doc(&'a self) -> Result<roxmltree::Document<'a>> {
         Ok(roxmltree::Document::parse(&self.data.0)?)
     }
+
+    /// Parses the resource as an EPUB 3 navigation document.
+    pub fn nav(&'a self) -> Result<NavDoc> {
+        NavDoc::parse(&self.doc()?).ok_or_else(|| anyhow::anyhow!("document has no nav element"))
+    }
 }
 
 impl<'a> Resource<media_type::Opf> {
-    pub fn content(&'a self) -> Result<Content> {
+    pub fn content(&'a self) -> Result<Content<'a>> {
         Ok(Content::from_str(&self.data.0)?)
     }
+
+    /// Reads only the metadata of the package, see [`PackageInfo`].
+    pub fn info(&'a self) -> Result<PackageInfo<'a>> {
+        PackageInfo::parse(&self.data.0)
+    }
 }
 
 impl<'a> Resource<media_type::DtbNcx> {
-    pub fn toc(&'a self) -> Result<TableOfContents> {
+    pub fn toc(&'a self) -> Result<TableOfContents<'a>> {
         Ok(TableOfContents::from_str(&self.data.0)?)
     }
 }
 
+/// Reference to a resource of the book, relative to the package document.
 pub struct Href<'a, Media> {
     url: Cow<'a, str>,
     phantom: PhantomData<Media>,
 }
 
 impl Href<'static, media_type::DtbNcx> {
+    /// Name of the NCX in books written by this crate, [`Content::ncx_href`] locates it in others.
     pub const TOC: Self = Self::new(Cow::Borrowed("toc.ncx"));
 }
 
 impl Href<'static, media_type::Opf> {
+    /// Conventional name of the package document, [`Epub::package`] reads the one the container declares.
     pub const CONTENT: Self = Self::new(Cow::Borrowed("content.opf"));
 }
 
@@ -267,6 +628,94 @@ impl<'a, Media> Href<'a, Media> {
     }
 }
 
+impl<'a> Href<'a, ()> {
+    /// Builds an href to a resource of any media type, for reads which don't interpret the contents.
+    /// The url is expected to be normalized already, like the ones taken from the manifest.
+    pub(crate) fn untyped(url: impl Into<Cow<'a, str>>) -> Self {
+        Self::new(url.into())
+    }
+}
+
+impl<'a, Media: media_type::MediaType> Href<'a, Media> {
+    /// Builds an href from a path relative to the package document, like the ones found in the manifest.
+    /// The path is normalized, urls which can't point into the book, such as absolute ones,
+    /// ones with a scheme or ones climbing above the root, are rejected.
+    pub fn try_new(url: impl Into<Cow<'a, str>>) -> Result<Self, InvalidHref> {
+        let url = url.into();
+        let (file, fragment) = match url.split_once('#') {
+            Some((file, fragment)) => (file, Some(fragment)),
+            None => (url.as_ref(), None),
+        };
+        let normalized = path::validate(file)?;
+        if normalized == file {
+            return Ok(Self::new(url));
+        }
+        let url = match fragment {
+            Some(fragment) => format!("{}#{}", normalized, fragment),
+            None => normalized,
+        };
+        Ok(Self::new(Cow::Owned(url)))
+    }
+
+    /// Checks that the manifest lists the file this href points to with the expected media type.
+    pub fn verify(&self, manifest: &Manifest) -> Result<(), InvalidHref> {
+        let file = path::normalize(self.url.split('#').next().unwrap_or_default());
+        let item = manifest
+            .items
+            .iter()
+            .find(|item| path::normalize(&item.href) == file)
+            .ok_or(InvalidHref::NotInManifest)?;
+        if item.media_type.as_ref() != Media::MEDIA_TYPE {
+            return Err(InvalidHref::MediaTypeMismatch {
+                expected: Media::MEDIA_TYPE,
+                found: item.media_type.clone().into_owned(),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Reason for rejecting a url passed to [`Href::try_new`] or [`Href::verify`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InvalidHref {
+    Empty,
+    /// The url is absolute, has a scheme or uses backslashes as separators.
+    NotRelative,
+    /// The path climbs above the root of the book.
+    EscapesRoot,
+    NotInManifest,
+    MediaTypeMismatch {
+        expected: &'static str,
+        found: String,
+    },
+}
+
+impl std::fmt::Display for InvalidHref {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InvalidHref::Empty => write!(f, "href is empty"),
+            InvalidHref::NotRelative => write!(f, "href is not a relative path"),
+            InvalidHref::EscapesRoot => write!(f, "href points outside of the book"),
+            InvalidHref::NotInManifest => write!(f, "href is not listed in the manifest"),
+            InvalidHref::MediaTypeMismatch { expected, found } => {
+                write!(
+                    f,
+                    "href points to a resource of type '{}', expected '{}'",
+                    found, expected
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for InvalidHref {}
+
+impl<'a, Media> Clone for Href<'a, Media> {
+    fn clone(&self) -> Self {
+        Self::new(self.url.clone())
+    }
+}
+
 impl<'a, Media> AsRef<str> for Href<'a, Media> {
     fn as_ref(&self) -> &str {
         self.url.as_ref()
@@ -283,6 +732,30 @@ impl TryFrom<Vec<u8>> for Utf8String {
     }
 }
 
+impl From<Utf8String> for String {
+    fn from(value: Utf8String) -> Self {
+        value.0
+    }
+}
+
+impl From<Utf8String> for Vec<u8> {
+    fn from(value: Utf8String) -> Self {
+        value.0.into_bytes()
+    }
+}
+
+impl AsRef<str> for Utf8String {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<[u8]> for Utf8String {
+    fn as_ref(&self) -> &[u8] {
+        self.0.as_bytes()
+    }
+}
+
 pub mod media_type {
     use super::Utf8String;
 
@@ -296,30 +769,144 @@ pub mod media_type {
     pub struct Svg;
 
     pub trait MediaType {
-        type Value;
+        type Value: AsRef<[u8]> + Into<Vec<u8>>;
+        const MEDIA_TYPE: &'static str;
     }
     impl MediaType for Opf {
         type Value = Utf8String;
+        const MEDIA_TYPE: &'static str = "application/oebps-package+xml";
     }
     impl MediaType for DtbNcx {
         type Value = Utf8String;
+        const MEDIA_TYPE: &'static str = "application/x-dtbncx+xml";
     }
     impl MediaType for XHtml {
         type Value = Utf8String;
+        const MEDIA_TYPE: &'static str = "application/xhtml+xml";
     }
     impl MediaType for Css {
         type Value = Utf8String;
+        const MEDIA_TYPE: &'static str = "text/css";
     }
     impl MediaType for Png {
         type Value = Vec<u8>;
+        const MEDIA_TYPE: &'static str = "image/png";
     }
     impl MediaType for Jpeg {
         type Value = Vec<u8>;
+        const MEDIA_TYPE: &'static str = "image/jpeg";
     }
     impl MediaType for Gif {
         type Value = Vec<u8>;
+        const MEDIA_TYPE: &'static str = "image/gif";
     }
     impl MediaType for Svg {
         type Value = Vec<u8>;
+        const MEDIA_TYPE: &'static str = "image/svg+xml";
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media_type::{Css, Jpeg, Png, XHtml};
+
+    #[test]
+    fn validates_hrefs() {
+        let href = Href::<XHtml>::try_new("text/./one.xhtml").unwrap();
+        assert_eq!(href.as_ref(), "text/one.xhtml");
+        let href = Href::<XHtml>::try_new("text/../text/one.xhtml#start").unwrap();
+        assert_eq!(href.as_ref(), "text/one.xhtml#start");
+        assert!(matches!(
+            Href::<XHtml>::try_new("one.xhtml#start").unwrap().url,
+            Cow::Borrowed(_)
+        ));
+
+        assert_eq!(Href::<XHtml>::try_new("").err(), Some(InvalidHref::Empty));
+        assert_eq!(Href::<XHtml>::try_new("#start").err(), Some(InvalidHref::Empty));
+        assert_eq!(Href::<XHtml>::try_new("text/..").err(), Some(InvalidHref::Empty));
+        assert_eq!(
+            Href::<XHtml>::try_new("/one.xhtml").err(),
+            Some(InvalidHref::NotRelative)
+        );
+        assert_eq!(
+            Href::<XHtml>::try_new("text\\one.xhtml").err(),
+            Some(InvalidHref::NotRelative)
+        );
+        assert_eq!(
+            Href::<XHtml>::try_new("https://example.com/one.xhtml").err(),
+            Some(InvalidHref::NotRelative)
+        );
+        assert_eq!(
+            Href::<XHtml>::try_new("../one.xhtml").err(),
+            Some(InvalidHref::EscapesRoot)
+        );
+    }
+
+    #[test]
+    fn verifies_hrefs_against_the_manifest() {
+        let package = testing::package(
+            r#"<item id="one" href="text/./one.xhtml" media-type="application/xhtml+xml"/>
+               <item id="css" href="style/book.css" media-type="text/css"/>"#,
+            &["one"],
+        );
+        let mut book = testing::book(&[("content.opf", &package)]);
+        let resource = book.package().unwrap();
+        let manifest = resource.content().unwrap().manifest;
+
+        assert_eq!(
+            Href::<XHtml>::try_new("text/one.xhtml#start")
+                .unwrap()
+                .verify(&manifest),
+            Ok(())
+        );
+        assert_eq!(
+            Href::<Css>::try_new("style/book.css").unwrap().verify(&manifest),
+            Ok(())
+        );
+        assert_eq!(
+            Href::<XHtml>::try_new("text/two.xhtml").unwrap().verify(&manifest),
+            Err(InvalidHref::NotInManifest)
+        );
+        assert_eq!(
+            Href::<XHtml>::try_new("style/book.css").unwrap().verify(&manifest),
+            Err(InvalidHref::MediaTypeMismatch {
+                expected: "application/xhtml+xml",
+                found: "text/css".to_owned(),
+            })
+        );
+    }
+
+    #[test]
+    fn converts_resources() {
+        let mut book = testing::book(&[("style.css", "p { margin: 0 }")]);
+        book.storage.insert("OEBPS/cover.png", vec![0x89, b'P', b'N', b'G']);
+
+        let css = book.read(Href::<Css>::try_new("style.css").unwrap()).unwrap();
+        assert_eq!(css.as_str(), "p { margin: 0 }");
+        assert_eq!(css.as_bytes(), b"p { margin: 0 }");
+        assert_eq!(css.as_ref(), b"p { margin: 0 }");
+        assert_eq!(css.len(), 15);
+        assert!(!css.is_empty());
+        assert_eq!(String::from(css), "p { margin: 0 }");
+
+        let css = book.read(Href::<Css>::try_new("style.css").unwrap()).unwrap();
+        assert_eq!(Vec::from(css), b"p { margin: 0 }");
+        let css = book.read(Href::<Css>::try_new("style.css").unwrap()).unwrap();
+        let xhtml = css.map::<XHtml>(|text| Utf8String(format!("<style>{}</style>", text.0)));
+        assert_eq!(xhtml.into_data().0, "<style>p { margin: 0 }</style>");
+
+        let png = book.read(Href::<Png>::try_new("cover.png").unwrap()).unwrap();
+        assert_eq!(png.as_bytes(), [0x89, b'P', b'N', b'G']);
+        assert_eq!(png.len(), 4);
+        let jpeg = png.map::<Jpeg>(|mut bytes| {
+            bytes.truncate(1);
+            bytes
+        });
+        assert_eq!(jpeg.len(), 1);
+        assert_eq!(Vec::from(jpeg), [0x89]);
+
+        let png = book.read(Href::<Png>::try_new("cover.png").unwrap()).unwrap();
+        assert_eq!(png.into_data(), [0x89, b'P', b'N', b'G']);
     }
 }
@@ -0,0 +1,74 @@
+/// Attributes through which documents reference other resources,
+/// `xlink:href` is covered by its local name.
+pub(crate) const LINK_ATTRIBUTES: &[&str] = &["href", "src", "poster"];
+
+/// Returns the relative urls referenced by a document.
+pub(crate) fn document_links<'a>(doc: &'a roxmltree::Document) -> impl Iterator<Item = &'a str> {
+    doc.descendants()
+        .flat_map(|node| node.attributes())
+        .filter(|attr| LINK_ATTRIBUTES.contains(&attr.name()))
+        .map(|attr| attr.value())
+        .filter(|url| crate::path::is_relative(url))
+}
+
+/// Returns the relative urls of the resources a document needs to be rendered: stylesheets, images,
+/// audio and video, including those referenced from its inline styles. Unlike [`document_links`]
+/// hyperlinks to other documents are left out.
+pub(crate) fn resource_links<'a>(doc: &'a roxmltree::Document) -> Vec<&'a str> {
+    let mut links = vec![];
+    for node in doc.descendants().filter(|node| node.is_element()) {
+        match node.tag_name().name() {
+            "link" => {
+                let stylesheet = node
+                    .attribute("rel")
+                    .is_some_and(|rel| rel.split_whitespace().any(|rel| rel.eq_ignore_ascii_case("stylesheet")));
+                if stylesheet {
+                    links.extend(node.attribute("href"));
+                }
+            }
+            "img" | "source" | "track" => links.extend(node.attribute("src")),
+            "audio" | "video" => links.extend(["src", "poster"].iter().filter_map(|name| node.attribute(*name))),
+            "image" => links.extend(
+                node.attributes()
+                    .iter()
+                    .find(|attr| attr.name() == "href")
+                    .map(|attr| attr.value()),
+            ),
+            "style" => links.extend(node.text().map(css_links).unwrap_or_default()),
+            _ => {}
+        }
+        links.extend(node.attribute("style").map(css_links).unwrap_or_default());
+    }
+    links.retain(|url| crate::path::is_relative(url));
+    links
+}
+
+/// Returns the relative urls referenced by a stylesheet through `url()` and `@import`.
+pub(crate) fn css_links(css: &str) -> Vec<&str> {
+    let mut links = vec![];
+
+    let mut rest = css;
+    while let Some(idx) = rest.find("url(") {
+        rest = &rest[idx + 4..];
+        let end = rest.find(')').unwrap_or(rest.len());
+        links.push(unquote(&rest[..end]));
+        rest = &rest[end..];
+    }
+
+    let mut rest = css;
+    while let Some(idx) = rest.find("@import") {
+        rest = rest[idx + 7..].trim_start();
+        if let Some(quote) = rest.chars().next().filter(|c| *c == '"' || *c == '\'') {
+            if let Some(end) = rest[1..].find(quote) {
+                links.push(&rest[1..end + 1]);
+            }
+        }
+    }
+
+    links.retain(|url| crate::path::is_relative(url));
+    links
+}
+
+fn unquote(str: &str) -> &str {
+    str.trim().trim_matches(|c| c == '"' || c == '\'').trim()
+}
@@ -0,0 +1,221 @@
+use anyhow::Result;
+
+use crate::storage::{NotFound, Storage};
+use crate::timeout::Timeout;
+use crate::{path, text, Content, Epub};
+
+const OPS_NAMESPACE: &str = "http://www.idpf.org/2007/ops";
+
+/// Table of contents of an EPUB 3 navigation document, the counterpart of the NCX
+/// [`crate::TableOfContents`] in books following EPUB 3.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NavDoc {
+    pub entries: Vec<NavEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NavEntry {
+    pub title: String,
+    /// Target of the entry relative to the navigation document, headings without a link have none.
+    pub href: Option<String>,
+    pub children: Vec<NavEntry>,
+}
+
+impl NavDoc {
+    /// Reads the `toc` nav element of a document, or the first nav element if none is marked as such.
+    pub fn parse(doc: &roxmltree::Document) -> Option<Self> {
+        let navs = doc.descendants().filter(|node| node.tag_name().name() == "nav");
+        let nav = navs
+            .clone()
+            .find(|node| {
+                node.attribute((OPS_NAMESPACE, "type"))
+                    .is_some_and(|kind| kind.split_whitespace().any(|kind| kind == "toc"))
+            })
+            .or_else(|| navs.clone().next())?;
+        let list = nav.children().find(|node| node.tag_name().name() == "ol");
+        let entries = list.map(parse_list).unwrap_or_default();
+        Some(Self { entries })
+    }
+
+    /// Exports the outline in the same format as [`crate::TableOfContents::to_json`],
+    /// `playOrder` is the position of the entry in document order.
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut order = 0;
+        self.entries.iter().map(|entry| entry.to_json(&mut order)).collect()
+    }
+}
+
+impl NavEntry {
+    fn to_json(&self, order: &mut u32) -> serde_json::Value {
+        *order += 1;
+        let play_order = *order;
+        serde_json::json!({
+            "title": self.title,
+            "href": self.href,
+            "playOrder": play_order,
+            "children": self.children.iter().map(|child| child.to_json(order)).collect::<Vec<_>>(),
+        })
+    }
+}
+
+impl<S: Storage> Epub<S> {
+    /// Exports the table of contents of the book in the format of [`crate::TableOfContents::to_json`],
+    /// for frontends which render navigation without handling XML. The navigation document is preferred
+    /// over the NCX, which is used when the navigation document is missing or unreadable, and hrefs are made
+    /// relative to the package document whichever of them is used.
+    /// Returns `None` if the book has neither.
+    pub fn outline(&mut self, content: &Content) -> Result<Option<serde_json::Value>> {
+        if let Some(href) = content.nav_href() {
+            match self.read(href.clone()).and_then(|resource| resource.nav()) {
+                Ok(nav) => {
+                    let mut outline = nav.to_json();
+                    rebase(&mut outline, href.as_ref());
+                    return Ok(Some(outline));
+                }
+                Err(err) if err.is::<Timeout>() => return Err(err),
+                Err(_) => {}
+            }
+        }
+        let ncx_href = match content.ncx_href() {
+            Some(href) => href,
+            None => return Ok(None),
+        };
+        match self.read(ncx_href.clone()) {
+            Ok(resource) => {
+                let mut outline = resource.toc()?.to_json();
+                rebase(&mut outline, ncx_href.as_ref());
+                Ok(Some(outline))
+            }
+            Err(err) if err.is::<NotFound>() => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// Resolves the hrefs of exported entries, relative to the document at `base`, against the package document.
+fn rebase(entries: &mut serde_json::Value, base: &str) {
+    for entry in entries.as_array_mut().into_iter().flatten() {
+        let href = entry["href"]
+            .as_str()
+            .filter(|href| path::is_relative(href) || href.starts_with('#'));
+        if let Some(href) = href {
+            entry["href"] = path::resolve_with_fragment(base, href).into();
+        }
+        rebase(&mut entry["children"], base);
+    }
+}
+
+fn parse_list(list: roxmltree::Node) -> Vec<NavEntry> {
+    list.children()
+        .filter(|node| node.tag_name().name() == "li")
+        .filter_map(|item| {
+            let label = item
+                .children()
+                .find(|node| matches!(node.tag_name().name(), "a" | "span"))?;
+            let children = item
+                .children()
+                .find(|node| node.tag_name().name() == "ol")
+                .map(parse_list)
+                .unwrap_or_default();
+            Some(NavEntry {
+                title: text::node_text(label),
+                href: label.attribute("href").map(str::to_owned),
+                children,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use strong_xml::XmlRead;
+
+    use super::*;
+    use crate::testing;
+    use crate::TableOfContents;
+
+    const NCX: &str = r#"<ncx xmlns="http://www.daisy.org/z3986/2005/ncx/" version="2005-1"><navMap>
+<navPoint id="p1" playOrder="1"><navLabel><text>Part One</text></navLabel><content src="text/one.xhtml"/>
+<navPoint id="p2"><navLabel><text> Chapter
+  1 </text></navLabel><content src="text/one.xhtml#c1"/></navPoint>
+</navPoint>
+<navPoint id="p3" playOrder="3"><navLabel><text>Part Two</text></navLabel><content src="text/two.xhtml"/></navPoint>
+</navMap></ncx>"#;
+
+    const NAV: &str = r#"<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops"><body>
+<nav epub:type="landmarks"><ol><li><a href="one.xhtml">Start</a></li></ol></nav>
+<nav epub:type="toc"><ol>
+<li><a href="one.xhtml">Part One</a><ol><li><a href="one.xhtml#c1">Chapter
+  1</a></li></ol></li>
+<li><a href="two.xhtml">Part Two</a></li>
+</ol></nav></body></html>"#;
+
+    fn outline(prefix: &str) -> serde_json::Value {
+        json!([
+            {
+                "title": "Part One",
+                "href": format!("{}one.xhtml", prefix),
+                "playOrder": 1,
+                "children": [{
+                    "title": "Chapter 1",
+                    "href": format!("{}one.xhtml#c1", prefix),
+                    "playOrder": 2,
+                    "children": [],
+                }],
+            },
+            {
+                "title": "Part Two",
+                "href": format!("{}two.xhtml", prefix),
+                "playOrder": 3,
+                "children": [],
+            },
+        ])
+    }
+
+    #[test]
+    fn ncx_and_nav_export_the_same_format() {
+        let toc = TableOfContents::from_str(NCX).unwrap();
+        assert_eq!(toc.to_json(), outline("text/"));
+
+        let doc = roxmltree::Document::parse(NAV).unwrap();
+        let nav = NavDoc::parse(&doc).unwrap();
+        assert_eq!(nav.to_json(), outline(""));
+    }
+
+    #[test]
+    fn outline_prefers_nav_and_resolves_hrefs_against_the_package() {
+        let package = testing::package(
+            r#"<item id="nav" href="text/nav.xhtml" media-type="application/xhtml+xml" properties="nav"/>
+               <item id="one" href="text/one.xhtml" media-type="application/xhtml+xml"/>
+               <item id="two" href="text/two.xhtml" media-type="application/xhtml+xml"/>"#,
+            &["one", "two"],
+        );
+        let mut book = testing::book(&[("content.opf", &package), ("text/nav.xhtml", NAV), ("toc.ncx", NCX)]);
+        let resource = book.package().unwrap();
+        let content = resource.content().unwrap();
+        assert_eq!(book.outline(&content).unwrap(), Some(outline("text/")));
+    }
+
+    #[test]
+    fn outline_falls_back_to_ncx() {
+        let package = testing::package(
+            r#"<item id="toc" href="nav/toc.ncx" media-type="application/x-dtbncx+xml"/>
+               <item id="nav" href="nav/nav.xhtml" media-type="application/xhtml+xml" properties="nav"/>
+               <item id="one" href="text/one.xhtml" media-type="application/xhtml+xml"/>"#,
+            &["one"],
+        );
+        let ncx = NCX.replace("text/", "../text/");
+        let mut book = testing::book(&[("content.opf", &package), ("nav/toc.ncx", &ncx)]);
+        let resource = book.package().unwrap();
+        let content = resource.content().unwrap();
+        assert_eq!(book.outline(&content).unwrap(), Some(outline("text/")));
+
+        book.storage
+            .insert("OEBPS/nav/nav.xhtml", testing::xhtml("<p>No nav</p>").into_bytes());
+        assert_eq!(book.outline(&content).unwrap(), Some(outline("text/")));
+
+        let mut book = testing::book(&[("content.opf", &package)]);
+        assert_eq!(book.outline(&content).unwrap(), None);
+    }
+}
@@ -0,0 +1,336 @@
+use std::borrow::Cow;
+
+use anyhow::Result;
+
+use crate::storage::Storage;
+use crate::timeout::Timeout;
+use crate::{path, text, Content, Epub, Head, Meta, NavContent, NavEntry, NavLabel, NavMap, NavPoint, TableOfContents};
+
+impl<S: Storage> Epub<S> {
+    /// Builds a table of contents out of the headings found in the spine documents,
+    /// for books which come without a usable one. Documents without headings contribute their title,
+    /// documents which can't be read or parsed are skipped.
+    /// The result is marked as synthesized, see [`TableOfContents::is_synthesized`].
+    pub fn synthesize_toc(&mut self, content: &Content) -> Result<TableOfContents<'static>> {
+        let deadline = self.parse_deadline();
+        let mut entries = vec![];
+        for document in self.spine_documents(content.spine_items(), deadline) {
+            let document = document?;
+            let doc = match document.doc() {
+                Some(doc) => doc,
+                None => continue,
+            };
+            let href = &document.href;
+
+            let headings = text::body(&doc)
+                .descendants()
+                .filter_map(|node| Some((heading_level(node.tag_name().name())?, node)))
+                .map(|(level, node)| (level, text::node_text(node), node.attribute("id")))
+                .filter(|(_, title, _)| !title.is_empty())
+                .collect::<Vec<_>>();
+
+            if headings.is_empty() {
+                let title = doc
+                    .descendants()
+                    .find(|node| node.tag_name().name() == "title")
+                    .map(text::node_text)
+                    .filter(|title| !title.is_empty());
+                if let Some(title) = title {
+                    entries.push((1, title, href.as_ref().to_owned()));
+                }
+            } else {
+                for (level, title, id) in headings {
+                    let src = match id {
+                        Some(id) => format!("{}#{}", href.as_ref(), id),
+                        None => href.as_ref().to_owned(),
+                    };
+                    entries.push((level, title, src));
+                }
+            }
+        }
+
+        let points = entries
+            .into_iter()
+            .enumerate()
+            .map(|(idx, (level, title, src))| (level, numbered_point(idx + 1, title, src)));
+
+        let toc = TableOfContents {
+            xmlns: Some(Cow::Borrowed(TableOfContents::NAMESPACE)),
+            version: Some(Cow::Borrowed(TableOfContents::VERSION)),
+            head: Some(Head {
+                meta: vec![Meta {
+                    name: Some(Cow::Borrowed(TableOfContents::SYNTHESIZED)),
+                    content: Some(Cow::Borrowed("true")),
+                }],
+            }),
+            map: NavMap { points: nest(points) },
+        };
+        Ok(toc)
+    }
+
+    /// Returns the table of contents of the book, read from the NCX or else from the navigation document,
+    /// and synthesized from the headings when neither exists or has any entries.
+    /// Srcs of the returned table are relative to the package document.
+    pub fn toc_or_synthesized(&mut self, content: &Content) -> Result<TableOfContents<'static>> {
+        let ncx = content
+            .ncx_href()
+            .map(|href| readable(self.read(href)))
+            .transpose()?
+            .flatten();
+        if let Some(resource) = ncx {
+            if let Ok(toc) = resource.toc() {
+                if !toc.is_empty() {
+                    return Ok(TableOfContents {
+                        xmlns: toc.xmlns.as_deref().map(owned),
+                        version: toc.version.as_deref().map(owned),
+                        head: toc.head.as_ref().map(|head| Head {
+                            meta: head
+                                .meta
+                                .iter()
+                                .map(|meta| Meta {
+                                    name: meta.name.as_deref().map(owned),
+                                    content: meta.content.as_deref().map(owned),
+                                })
+                                .collect(),
+                        }),
+                        map: NavMap {
+                            points: toc
+                                .points()
+                                .iter()
+                                .map(|point| rebased_point(point, &content.ncx_base()))
+                                .collect(),
+                        },
+                    });
+                }
+            }
+        }
+
+        if let Some(href) = content.nav_href() {
+            if let Some(resource) = readable(self.read(href.clone()))? {
+                if let Ok(nav) = resource.nav() {
+                    let points = nav_points(&nav.entries, href.as_ref(), &mut 0);
+                    if !points.is_empty() {
+                        return Ok(TableOfContents {
+                            xmlns: Some(Cow::Borrowed(TableOfContents::NAMESPACE)),
+                            version: Some(Cow::Borrowed(TableOfContents::VERSION)),
+                            head: None,
+                            map: NavMap { points },
+                        });
+                    }
+                }
+            }
+        }
+
+        self.synthesize_toc(content)
+    }
+}
+
+/// Treats failures to read a resource as its absence, except for running out of time.
+fn readable<T>(result: Result<T>) -> Result<Option<T>> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(err) if err.is::<Timeout>() => Err(err),
+        Err(_) => Ok(None),
+    }
+}
+
+fn owned(str: &str) -> Cow<'static, str> {
+    Cow::Owned(str.to_owned())
+}
+
+/// Copies a point of a table located at `base`, resolving its srcs against the package document.
+pub(crate) fn rebased_point(point: &NavPoint, base: &str) -> NavPoint<'static> {
+    let src = path::resolve_with_fragment(base, &point.content.src).unwrap_or_else(|| point.content.src.to_string());
+    NavPoint {
+        id: point.id.as_deref().map(owned),
+        play_order: point.play_order.as_deref().map(owned),
+        label: NavLabel {
+            text: owned(&point.label.text),
+        },
+        content: NavContent { src: Cow::Owned(src) },
+        children: point.children.iter().map(|child| rebased_point(child, base)).collect(),
+    }
+}
+
+/// Converts entries of a navigation document located at `base` into nav points.
+/// Headings without a link target their first linked descendant, or are left out with their children
+/// taking their place if there is none.
+fn nav_points(entries: &[NavEntry], base: &str, order: &mut usize) -> Vec<NavPoint<'static>> {
+    let mut points = vec![];
+    for entry in entries {
+        let src = entry
+            .href
+            .as_deref()
+            .or_else(|| first_href(&entry.children))
+            .filter(|href| path::is_relative(href) || href.starts_with('#'))
+            .and_then(|href| path::resolve_with_fragment(base, href));
+        match src {
+            Some(src) => {
+                *order += 1;
+                let mut point = numbered_point(*order, entry.title.clone(), src);
+                point.children = nav_points(&entry.children, base, order);
+                points.push(point);
+            }
+            None => points.extend(nav_points(&entry.children, base, order)),
+        }
+    }
+    points
+}
+
+fn first_href(entries: &[NavEntry]) -> Option<&str> {
+    entries
+        .iter()
+        .find_map(|entry| entry.href.as_deref().or_else(|| first_href(&entry.children)))
+}
+
+fn heading_level(tag: &str) -> Option<u8> {
+    match tag {
+        "h1" => Some(1),
+        "h2" => Some(2),
+        "h3" => Some(3),
+        "h4" => Some(4),
+        "h5" => Some(5),
+        "h6" => Some(6),
+        _ => None,
+    }
+}
+
+fn numbered_point(order: usize, title: String, src: String) -> NavPoint<'static> {
+    NavPoint {
+        id: Some(Cow::Owned(format!("navPoint-{}", order))),
+        play_order: Some(Cow::Owned(order.to_string())),
+        label: NavLabel {
+            text: Cow::Owned(title),
+        },
+        content: NavContent { src: Cow::Owned(src) },
+        children: vec![],
+    }
+}
+
+/// Turns a flat list of headings into a tree, nesting each one under the closest preceding heading of a higher rank.
+fn nest<'a>(entries: impl Iterator<Item = (u8, NavPoint<'a>)>) -> Vec<NavPoint<'a>> {
+    fn close<'a>(stack: &mut Vec<(u8, NavPoint<'a>)>, roots: &mut Vec<NavPoint<'a>>) {
+        if let Some((_, point)) = stack.pop() {
+            match stack.last_mut() {
+                Some((_, parent)) => parent.children.push(point),
+                None => roots.push(point),
+            }
+        }
+    }
+
+    let mut roots = vec![];
+    let mut stack: Vec<(u8, NavPoint)> = vec![];
+    for (level, point) in entries {
+        while stack.last().is_some_and(|(top, _)| *top >= level) {
+            close(&mut stack, &mut roots);
+        }
+        stack.push((level, point));
+    }
+    while !stack.is_empty() {
+        close(&mut stack, &mut roots);
+    }
+    roots
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    const ITEMS: &str = r#"<item id="ncx" href="toc.ncx" media-type="application/x-dtbncx+xml"/>
+        <item id="nav" href="text/nav.xhtml" media-type="application/xhtml+xml" properties="nav"/>
+        <item id="one" href="text/one.xhtml" media-type="application/xhtml+xml"/>
+        <item id="gone" href="text/gone.xhtml" media-type="application/xhtml+xml"/>
+        <item id="latin1" href="text/latin1.xhtml" media-type="application/xhtml+xml"/>
+        <item id="two" href="text/two.xhtml" media-type="application/xhtml+xml"/>"#;
+    const SPINE: &[&str] = &["one", "gone", "latin1", "two"];
+
+    fn titles(points: &[NavPoint]) -> Vec<String> {
+        points
+            .iter()
+            .map(|point| {
+                let children = titles(&point.children);
+                if children.is_empty() {
+                    format!("{} -> {}", point.label.text, point.href().as_ref())
+                } else {
+                    format!("{} -> {} {:?}", point.label.text, point.href().as_ref(), children)
+                }
+            })
+            .collect()
+    }
+
+    fn book(extra: &[(&str, &str)]) -> Epub<crate::storage::MemoryStorage> {
+        let package = testing::package(ITEMS, SPINE);
+        let one = testing::xhtml(r#"<h1 id="a">One</h1><h2 id="b">One point one</h2>"#);
+        let two = testing::xhtml("<h1>Two</h1>");
+        let mut files = vec![
+            ("content.opf", package.as_str()),
+            ("text/one.xhtml", &one),
+            ("text/two.xhtml", &two),
+        ];
+        files.extend_from_slice(extra);
+        let mut book = testing::book(&files);
+        book.storage.insert(
+            "OEBPS/text/latin1.xhtml",
+            b"<html><body><h1>Caf\xe9</h1></body></html>".to_vec(),
+        );
+        book
+    }
+
+    #[test]
+    fn synthesize_skips_unreadable_documents() {
+        let mut book = book(&[]);
+        let resource = book.package().unwrap();
+        let content = resource.content().unwrap();
+        let toc = book.synthesize_toc(&content).unwrap();
+        assert!(toc.is_synthesized());
+        assert_eq!(
+            titles(toc.points()),
+            [
+                r#"One -> text/one.xhtml#a ["One point one -> text/one.xhtml#b"]"#,
+                "Two -> text/two.xhtml",
+            ]
+        );
+    }
+
+    #[test]
+    fn existing_ncx_is_preferred() {
+        let ncx = r#"<ncx xmlns="http://www.daisy.org/z3986/2005/ncx/" version="2005-1"><navMap>
+            <navPoint><navLabel><text>Second</text></navLabel><content src="text/two.xhtml"/></navPoint>
+            </navMap></ncx>"#;
+        let mut book = book(&[("toc.ncx", ncx)]);
+        let resource = book.package().unwrap();
+        let content = resource.content().unwrap();
+        let toc = book.toc_or_synthesized(&content).unwrap();
+        assert!(!toc.is_synthesized());
+        assert_eq!(titles(toc.points()), ["Second -> text/two.xhtml"]);
+    }
+
+    #[test]
+    fn nav_document_is_used_without_ncx() {
+        let nav = r#"<html xmlns="http://www.w3.org/1999/xhtml"><body><nav><ol>
+            <li><span>Part</span><ol><li><a href="one.xhtml#a">One</a></li><li><a href="two.xhtml">Two</a></li></ol></li>
+            </ol></nav></body></html>"#;
+        let mut book = book(&[("text/nav.xhtml", nav)]);
+        let resource = book.package().unwrap();
+        let content = resource.content().unwrap();
+        let toc = book.toc_or_synthesized(&content).unwrap();
+        assert!(!toc.is_synthesized());
+        assert_eq!(
+            titles(toc.points()),
+            [r#"Part -> text/one.xhtml#a ["One -> text/one.xhtml#a", "Two -> text/two.xhtml"]"#]
+        );
+    }
+
+    #[test]
+    fn empty_tables_are_synthesized() {
+        let ncx = r#"<ncx xmlns="http://www.daisy.org/z3986/2005/ncx/" version="2005-1"><navMap/></ncx>"#;
+        let nav = testing::xhtml("<nav><ol/></nav>");
+        let mut book = book(&[("toc.ncx", ncx), ("text/nav.xhtml", &nav)]);
+        let resource = book.package().unwrap();
+        let content = resource.content().unwrap();
+        let toc = book.toc_or_synthesized(&content).unwrap();
+        assert!(toc.is_synthesized());
+        assert_eq!(toc.points().len(), 2);
+    }
+}
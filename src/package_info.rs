@@ -0,0 +1,298 @@
+use std::borrow::Cow;
+
+use anyhow::{anyhow, Result};
+
+/// Metadata of a package document read by a fast path which borrows from the document and stops
+/// at the end of the metadata, without building a tree or reading the manifest and the spine.
+/// Meant for scanning large libraries where only the metadata is needed, use [`crate::Content`]
+/// for anything else.
+///
+/// Values are borrowed unless they contain entity or character references or are split
+/// by comments or CDATA sections.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PackageInfo<'a> {
+    pub version: Option<Cow<'a, str>>,
+    pub unique_identifier: Option<Cow<'a, str>>,
+    pub title: Option<Cow<'a, str>>,
+    pub language: Option<Cow<'a, str>>,
+    /// The identifier referenced by `unique-identifier`, or the first one if there is no such identifier.
+    pub identifier: Option<Cow<'a, str>>,
+}
+
+impl<'a> PackageInfo<'a> {
+    pub fn parse(xml: &'a str) -> Result<Self> {
+        let mut info = Self::default();
+        let mut found_package = false;
+        let mut in_metadata = false;
+        let mut unique_identifier_found = false;
+
+        let mut rest = xml;
+        while let Some(start) = rest.find('<') {
+            rest = &rest[start..];
+            if let Some(skipped) = skip_markup(rest) {
+                rest = skipped;
+                continue;
+            }
+            if let Some(closing) = rest.strip_prefix("</") {
+                let end = closing.find('>').ok_or_else(unterminated)?;
+                if local_name(closing[..end].trim()) == "metadata" {
+                    break;
+                }
+                rest = &closing[end + 1..];
+                continue;
+            }
+
+            let end = tag_end(rest).ok_or_else(unterminated)?;
+            let tag = &rest[1..end];
+            rest = &rest[end + 1..];
+            let self_closing = tag.ends_with('/');
+            let tag = tag.trim_end_matches('/');
+            let (name, attributes) = tag.split_at(tag.find(char::is_whitespace).unwrap_or(tag.len()));
+
+            match local_name(name) {
+                "package" => {
+                    found_package = true;
+                    info.version = attribute(attributes, "version").map(decode);
+                    info.unique_identifier = attribute(attributes, "unique-identifier").map(decode);
+                }
+                "metadata" => in_metadata = true,
+                field @ ("title" | "language" | "identifier") if in_metadata && !self_closing => {
+                    let text = element_text(rest);
+                    match field {
+                        "title" if info.title.is_none() => info.title = Some(text),
+                        "language" if info.language.is_none() => info.language = Some(text),
+                        "identifier" if !unique_identifier_found => {
+                            let id = attribute(attributes, "id");
+                            if id.is_some() && id == info.unique_identifier.as_deref() {
+                                unique_identifier_found = true;
+                                info.identifier = Some(text);
+                            } else if info.identifier.is_none() {
+                                info.identifier = Some(text);
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if !found_package {
+            return Err(anyhow!("document is not a package document"));
+        }
+        Ok(info)
+    }
+}
+
+fn unterminated() -> anyhow::Error {
+    anyhow!("unterminated tag in package document")
+}
+
+/// Skips over declarations, processing instructions and comments, returning the rest of the document.
+fn skip_markup(xml: &str) -> Option<&str> {
+    let (terminator, start) = if xml.starts_with("<?") {
+        ("?>", 2)
+    } else if xml.starts_with("<!--") {
+        ("-->", 4)
+    } else if xml.starts_with("<![CDATA[") {
+        ("]]>", 9)
+    } else if xml.starts_with("<!") {
+        (">", 2)
+    } else {
+        return None;
+    };
+    let end = xml[start..].find(terminator).map(|end| start + end + terminator.len());
+    Some(&xml[end.unwrap_or(xml.len())..])
+}
+
+/// Returns the text of an element, given the document following its start tag, up to the next tag.
+/// Comments and processing instructions are skipped and CDATA sections are taken as they are.
+fn element_text(xml: &str) -> Cow<'_, str> {
+    let mut segments = vec![];
+    let mut rest = xml;
+    loop {
+        let end = rest.find('<').unwrap_or(rest.len());
+        if end > 0 {
+            segments.push(decode(&rest[..end]));
+        }
+        rest = &rest[end..];
+        if let Some(cdata) = rest.strip_prefix("<![CDATA[") {
+            let end = cdata.find("]]>").unwrap_or(cdata.len());
+            segments.push(Cow::Borrowed(&cdata[..end]));
+            rest = cdata.get(end + 3..).unwrap_or_default();
+        } else if let Some(skipped) = skip_markup(rest) {
+            rest = skipped;
+        } else {
+            break;
+        }
+    }
+    match segments.len() {
+        0 => Cow::Borrowed(""),
+        1 => match segments.remove(0) {
+            Cow::Borrowed(text) => Cow::Borrowed(text.trim()),
+            Cow::Owned(text) => Cow::Owned(text.trim().to_owned()),
+        },
+        _ => Cow::Owned(segments.concat().trim().to_owned()),
+    }
+}
+
+/// Finds the closing bracket of a start tag, ignoring brackets inside quoted attribute values.
+fn tag_end(xml: &str) -> Option<usize> {
+    let mut quote = None;
+    for (idx, char) in xml.char_indices() {
+        match (quote, char) {
+            (None, '"' | '\'') => quote = Some(char),
+            (Some(open), _) if char == open => quote = None,
+            (None, '>') => return Some(idx),
+            _ => {}
+        }
+    }
+    None
+}
+
+fn local_name(name: &str) -> &str {
+    name.rsplit(':').next().unwrap_or(name)
+}
+
+/// Finds an attribute by its qualified name in the attribute part of a tag.
+fn attribute<'a>(mut attributes: &'a str, name: &str) -> Option<&'a str> {
+    loop {
+        attributes = attributes.trim_start();
+        let eq = attributes.find('=')?;
+        let key = attributes[..eq].trim();
+        let value = attributes[eq + 1..].trim_start();
+        let quote = value.chars().next().filter(|char| matches!(char, '"' | '\''))?;
+        let end = value[1..].find(quote)? + 1;
+        if key == name {
+            return Some(&value[1..end]);
+        }
+        attributes = &value[end + 1..];
+    }
+}
+
+/// Replaces entity and character references, borrowing the input if there are none.
+fn decode(text: &str) -> Cow<'_, str> {
+    if !text.contains('&') {
+        return Cow::Borrowed(text);
+    }
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = match rest.find(';') {
+            Some(end) => end,
+            None => break,
+        };
+        let char = match &rest[1..end] {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            reference => reference
+                .strip_prefix("#x")
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| reference.strip_prefix('#').map(str::parse))
+                .and_then(Result::ok)
+                .and_then(char::from_u32),
+        };
+        match char {
+            Some(char) => {
+                decoded.push(char);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    Cow::Owned(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use strong_xml::XmlRead;
+
+    use super::*;
+    use crate::Content;
+
+    /// Package document as written by Calibre, with OPF attributes and a cover record.
+    const CALIBRE: &str = r#"<?xml version='1.0' encoding='utf-8'?>
+<package xmlns="http://www.idpf.org/2007/opf" unique-identifier="uuid_id" version="2.0">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:opf="http://www.idpf.org/2007/opf">
+    <dc:title>Pride &amp; Prejudice</dc:title>
+    <dc:creator opf:role="aut" opf:file-as="Austen, Jane">Jane Austen</dc:creator>
+    <dc:identifier opf:scheme="uuid" id="uuid_id">6f1c2c8e-3e1b-4a8e-9d6a-0c1b2d3e4f50</dc:identifier>
+    <dc:language>en</dc:language>
+    <meta name="cover" content="cover"/>
+    <meta name="calibre:timestamp" content="2021-03-04T10:11:12+00:00"/>
+  </metadata>
+  <manifest>
+    <item href="titlepage.xhtml" id="titlepage" media-type="application/xhtml+xml"/>
+    <item href="toc.ncx" id="ncx" media-type="application/x-dtbncx+xml"/>
+  </manifest>
+  <spine toc="ncx"><itemref idref="titlepage"/></spine>
+  <guide><reference href="titlepage.xhtml" title="Cover" type="cover"/></guide>
+</package>"#;
+
+    /// EPUB 3 package with prefixed attributes, refinements and a title in a CDATA section.
+    const EPUB3: &str = r##"<?xml version="1.0" encoding="UTF-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0" xml:lang="ja" unique-identifier="pub-id"
+    prefix="rendition: http://www.idpf.org/vocab/rendition/#">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:identifier id="pub-id">urn:isbn:9784000000000</dc:identifier>
+    <dc:title id="title"><![CDATA[Tom & Jerry <Vol. 1>]]></dc:title>
+    <meta refines="#title" property="title-type">main</meta>
+    <dc:language>ja</dc:language>
+    <meta property="dcterms:modified">2022-01-01T00:00:00Z</meta>
+  </metadata>
+  <manifest>
+    <item id="nav" href="nav.xhtml" media-type="application/xhtml+xml" properties="nav"/>
+  </manifest>
+  <spine page-progression-direction="rtl"><itemref idref="nav"/></spine>
+  <guide/>
+</package>"##;
+
+    /// Package with character references and without an XML declaration.
+    const REFERENCES: &str = r#"<package xmlns="http://www.idpf.org/2007/opf" version="2.0" unique-identifier="BookId">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:title>Caf&#233; &#x2014; Stories</dc:title>
+    <dc:language>fr-CA</dc:language>
+    <dc:identifier id="BookId">urn:uuid:0a1b2c3d</dc:identifier>
+  </metadata>
+  <manifest/>
+  <spine/>
+  <guide/>
+</package>"#;
+
+    #[test]
+    fn matches_the_full_parser() {
+        for xml in [CALIBRE, EPUB3, REFERENCES] {
+            let info = PackageInfo::parse(xml).unwrap();
+            let content = Content::from_str(xml).unwrap();
+            assert_eq!(info.version, content.version);
+            assert_eq!(info.unique_identifier, content.unique_identifier);
+            assert_eq!(info.title.as_deref(), Some(content.metadata.title.as_ref()));
+            assert_eq!(info.language.as_deref(), Some(content.metadata.language.as_ref()));
+            assert_eq!(info.identifier.as_deref(), Some(content.metadata.identifier.as_ref()));
+        }
+    }
+
+    #[test]
+    fn reads_text_around_comments_and_cdata() {
+        let xml = r#"<package version="2.0" unique-identifier="isbn"><metadata>
+            <dc:title><![CDATA[Foo & Bar]]></dc:title>
+            <dc:language><!-- primary -->en</dc:language>
+            <dc:identifier id="uuid">urn:uuid:1</dc:identifier>
+            <dc:identifier id="isbn"> 978<!-- check digit follows -->0<?pi?> </dc:identifier>
+        </metadata></package>"#;
+        let info = PackageInfo::parse(xml).unwrap();
+        assert_eq!(info.title.as_deref(), Some("Foo & Bar"));
+        assert_eq!(info.language.as_deref(), Some("en"));
+        assert_eq!(info.identifier.as_deref(), Some("9780"));
+        assert!(matches!(info.title, Some(Cow::Borrowed(_))));
+    }
+}
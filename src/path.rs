@@ -0,0 +1,133 @@
+use crate::InvalidHref;
+
+/// Returns the directory part of a slash-separated path, including the trailing slash.
+pub(crate) fn parent(path: &str) -> &str {
+    path.rfind('/').map(|idx| &path[..=idx]).unwrap_or("")
+}
+
+/// Returns true if the url is a plain relative reference to another resource in the book.
+pub(crate) fn is_relative(url: &str) -> bool {
+    !url.is_empty() && !url.starts_with('#') && !url.starts_with('/') && !has_scheme(url)
+}
+
+fn has_scheme(url: &str) -> bool {
+    match url.split_once(':') {
+        Some((scheme, _)) => {
+            !scheme.is_empty()
+                && !scheme.contains('/')
+                && scheme
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.')
+        }
+        None => false,
+    }
+}
+
+/// Resolves a relative url against the document it appears in, dropping any fragment or query.
+/// Returns `None` if the result would escape the root of the book.
+pub(crate) fn resolve(base: &str, url: &str) -> Option<String> {
+    let url = url.split(['#', '?']).next().unwrap_or_default();
+    normalize(&(parent(base).to_owned() + url))
+}
+
+/// Resolves a url like [`resolve`], but keeps its fragment. Fragment-only urls point into `base` itself.
+pub(crate) fn resolve_with_fragment(base: &str, url: &str) -> Option<String> {
+    let (file, fragment) = match url.split_once('#') {
+        Some((file, fragment)) => (file, Some(fragment)),
+        None => (url, None),
+    };
+    let file = if file.is_empty() {
+        normalize(base)?
+    } else {
+        resolve(base, file)?
+    };
+    match fragment {
+        Some(fragment) => Some(format!("{}#{}", file, fragment)),
+        None => Some(file),
+    }
+}
+
+/// Collapses `.` and `..` segments and duplicate slashes.
+/// Returns `None` if the path climbs above the root.
+pub(crate) fn normalize(path: &str) -> Option<String> {
+    let mut segments: Vec<&str> = vec![];
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop()?;
+            }
+            _ => segments.push(segment),
+        }
+    }
+    Some(segments.join("/"))
+}
+
+/// Normalizes a path without a fragment given by a user of the crate, rejecting paths which can't
+/// point at a file in the book. Shared by [`crate::Href::try_new`] and [`crate::ContainerHref::try_new`].
+pub(crate) fn validate(path: &str) -> Result<String, InvalidHref> {
+    if path.is_empty() || path.starts_with('#') {
+        return Err(InvalidHref::Empty);
+    }
+    if path.contains('\\') || !is_relative(path) {
+        return Err(InvalidHref::NotRelative);
+    }
+    match normalize(path) {
+        Some(normalized) if normalized.is_empty() => Err(InvalidHref::Empty),
+        Some(normalized) => Ok(normalized),
+        None => Err(InvalidHref::EscapesRoot),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_paths() {
+        assert_eq!(
+            normalize("OEBPS/./text//one.xhtml").as_deref(),
+            Some("OEBPS/text/one.xhtml")
+        );
+        assert_eq!(
+            normalize("OEBPS/text/../images/a.png").as_deref(),
+            Some("OEBPS/images/a.png")
+        );
+        assert_eq!(normalize("OEBPS/../.."), None);
+        assert_eq!(normalize("./").as_deref(), Some(""));
+    }
+
+    #[test]
+    fn resolves_relative_to_the_document() {
+        assert_eq!(
+            resolve("OEBPS/text/one.xhtml", "../images/a.png?v=2").as_deref(),
+            Some("OEBPS/images/a.png")
+        );
+        assert_eq!(
+            resolve("OEBPS/text/one.xhtml", "two.xhtml#note").as_deref(),
+            Some("OEBPS/text/two.xhtml")
+        );
+        assert_eq!(
+            resolve("content.opf", "text/one.xhtml").as_deref(),
+            Some("text/one.xhtml")
+        );
+        assert_eq!(resolve("OEBPS/content.opf", "../../one.xhtml"), None);
+        assert_eq!(
+            resolve_with_fragment("text/one.xhtml", "#note").as_deref(),
+            Some("text/one.xhtml#note")
+        );
+        assert_eq!(
+            resolve_with_fragment("nav/toc.ncx", "../text/two.xhtml#start").as_deref(),
+            Some("text/two.xhtml#start")
+        );
+    }
+
+    #[test]
+    fn recognizes_relative_urls() {
+        assert!(is_relative("text/one.xhtml"));
+        assert!(is_relative("./c:d.xhtml"));
+        assert!(!is_relative("https://example.com/a.png"));
+        assert!(!is_relative("/OEBPS/one.xhtml"));
+        assert!(!is_relative("#note"));
+    }
+}
@@ -0,0 +1,336 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::time::SystemTime;
+
+use anyhow::Result;
+
+use crate::diagnostics::{self, Diagnostic};
+use crate::storage::{NotFound, Storage};
+use crate::timeout::Timeout;
+use crate::{path, ContainerHref, Epub, NavPoint};
+
+/// Keeps the results of validating a book between runs and on each refresh re-checks only
+/// the files that changed since the previous one and the files referencing them.
+/// Meant for editors watching a book unpacked with [`Epub::open_dir`].
+///
+/// Files are considered unchanged when the storage reports the same modification time,
+/// otherwise they are read and compared by a hash of their contents.
+#[derive(Debug, Clone, Default)]
+pub struct Revalidator {
+    files: HashMap<String, FileState>,
+    documents: BTreeMap<String, Vec<Diagnostic>>,
+    package: Vec<Diagnostic>,
+    navigation: Vec<Diagnostic>,
+    nav_targets: HashSet<String>,
+    initialized: bool,
+}
+
+#[derive(Debug, Clone)]
+struct FileState {
+    modified: Option<SystemTime>,
+    hash: u64,
+    links: HashSet<String>,
+}
+
+impl Revalidator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Brings the diagnostics up to date with the current state of the book and returns all of them.
+    /// The refresh as a whole runs within the [`crate::timeout::Timeouts::validate`] budget of the book.
+    /// A package document or NCX which can't be parsed is reported as [`Diagnostic::MalformedDocument`].
+    /// A refresh which fails, like on a timeout, keeps the state of the previous one,
+    /// so the next refresh checks the same changes again.
+    pub fn refresh<S: Storage>(&mut self, book: &mut Epub<S>) -> Result<Vec<Diagnostic>> {
+        let mut next = self.clone();
+        next.update(book)?;
+        *self = next;
+        Ok(self.diagnostics())
+    }
+
+    fn update<S: Storage>(&mut self, book: &mut Epub<S>) -> Result<()> {
+        let deadline = book.validate_deadline();
+        let entries = book.storage.entries()?;
+        let present: HashSet<String> = entries.iter().map(|entry| entry.name.clone()).collect();
+
+        let mut changed: HashSet<String> = self
+            .files
+            .keys()
+            .filter(|name| !present.contains(*name))
+            .cloned()
+            .collect();
+        for name in &changed {
+            self.files.remove(name);
+            self.documents.remove(name);
+        }
+
+        let mut checked = HashSet::new();
+        for entry in entries {
+            deadline.check()?;
+            let state = self.files.get_mut(&entry.name);
+            if let Some(state) = &state {
+                if entry.modified.is_some() && state.modified == entry.modified {
+                    continue;
+                }
+            }
+            let bytes = book.read_container_bytes(&ContainerHref::entry(entry.name.as_str()), deadline)?;
+            let hash = hash(&bytes);
+            if let Some(state) = state {
+                if state.hash == hash {
+                    state.modified = entry.modified;
+                    continue;
+                }
+            }
+
+            let links = self.check(&entry.name, &bytes, &present);
+            self.files.insert(
+                entry.name.clone(),
+                FileState {
+                    modified: entry.modified,
+                    hash,
+                    links,
+                },
+            );
+            checked.insert(entry.name.clone());
+            changed.insert(entry.name);
+        }
+
+        if self.initialized && changed.is_empty() {
+            return Ok(());
+        }
+
+        let dependents: Vec<String> = self
+            .files
+            .iter()
+            .filter(|(name, state)| !checked.contains(*name) && !state.links.is_disjoint(&changed))
+            .map(|(name, _)| name.clone())
+            .collect();
+        for name in dependents {
+            deadline.check()?;
+            let bytes = book.read_container_bytes(&ContainerHref::entry(name.as_str()), deadline)?;
+            let links = self.check(&name, &bytes, &present);
+            if let Some(state) = self.files.get_mut(&name) {
+                state.links = links;
+            }
+        }
+
+        let package = book.package.clone().into_string();
+        let package_changed = !self.initialized || changed.contains(&package);
+        let navigation_changed = package_changed || !self.nav_targets.is_disjoint(&changed);
+
+        if package_changed || navigation_changed {
+            let resource = match book.package_within(deadline) {
+                Ok(resource) => resource,
+                Err(err) if err.is::<Timeout>() || err.is::<NotFound>() => return Err(err),
+                Err(err) => {
+                    self.malformed_package(book, package, err);
+                    return Ok(());
+                }
+            };
+            let content = match resource.content() {
+                Ok(content) => content,
+                Err(err) => {
+                    self.malformed_package(book, package, err);
+                    return Ok(());
+                }
+            };
+            if package_changed {
+                self.package = duplicate_entries(book);
+                self.package.extend(content.check_manifest());
+            }
+            if navigation_changed {
+                self.navigation.clear();
+                self.nav_targets.clear();
+                let ncx = content.ncx_href().and_then(|href| Some((book.resolve(&href)?, href)));
+                if let Some((ncx_path, ncx_href)) = ncx {
+                    self.nav_targets.insert(ncx_path.clone().into_string());
+                    if let Some(nav) = content.nav_href().and_then(|nav| book.resolve(&nav)) {
+                        self.nav_targets.insert(nav.into_string());
+                    }
+                    match book.read_within(ncx_href, deadline) {
+                        Ok(resource) => match resource.toc() {
+                            Ok(toc) => {
+                                self.navigation = book.check_nav_targets_within(&content, &toc, deadline)?;
+                                self.navigation
+                                    .extend(book.check_toc_consistency_within(&content, &toc, deadline)?);
+                                self.nav_targets.extend(nav_targets(ncx_path.as_ref(), toc.points()));
+                            }
+                            Err(err) => self.navigation.push(Diagnostic::MalformedDocument {
+                                file: ncx_path.into_string(),
+                                message: err.to_string(),
+                            }),
+                        },
+                        Err(err) if err.is::<Timeout>() => return Err(err),
+                        Err(_) => {}
+                    }
+                }
+            }
+        }
+
+        self.initialized = true;
+        Ok(())
+    }
+
+    /// Returns the diagnostics as of the last refresh.
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        self.package
+            .iter()
+            .chain(&self.navigation)
+            .chain(self.documents.values().flatten())
+            .cloned()
+            .collect()
+    }
+
+    fn malformed_package<S: Storage>(&mut self, book: &Epub<S>, file: String, err: anyhow::Error) {
+        self.package = duplicate_entries(book);
+        self.package.push(Diagnostic::MalformedDocument {
+            file,
+            message: err.to_string(),
+        });
+        self.navigation.clear();
+        self.nav_targets.clear();
+        self.initialized = true;
+    }
+
+    fn check(&mut self, name: &str, bytes: &[u8], present: &HashSet<String>) -> HashSet<String> {
+        if !diagnostics::is_document(name) {
+            return HashSet::new();
+        }
+        let (found, links) = diagnostics::check_document(name, bytes, |target| present.contains(target));
+        self.documents.insert(name.to_owned(), found);
+        links
+    }
+}
+
+fn duplicate_entries<S: Storage>(book: &Epub<S>) -> Vec<Diagnostic> {
+    book.storage
+        .duplicates()
+        .into_iter()
+        .map(|name| Diagnostic::DuplicateEntry { name })
+        .collect()
+}
+
+fn nav_targets(toc: &str, points: &[NavPoint]) -> HashSet<String> {
+    let mut targets = HashSet::new();
+    let mut pending: Vec<&NavPoint> = points.iter().collect();
+    while let Some(point) = pending.pop() {
+        pending.extend(&point.children);
+        if let Some(file) = path::resolve(toc, &point.content.src) {
+            targets.insert(file);
+        }
+    }
+    targets
+}
+
+fn hash(bytes: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    bytes.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::testing::{book, package, xhtml};
+
+    const ITEMS: &str = r#"<item id="one" href="one.xhtml" media-type="application/xhtml+xml"/>
+        <item id="two" href="two.xhtml" media-type="application/xhtml+xml"/>"#;
+
+    fn broken(file: &str, href: &str) -> Diagnostic {
+        Diagnostic::BrokenLink {
+            file: file.to_owned(),
+            href: href.to_owned(),
+        }
+    }
+
+    #[test]
+    fn rechecks_changed_files_and_their_dependents() {
+        let opf = package(ITEMS, &["one", "two"]);
+        let one = xhtml(r#"<p><a href="two.xhtml">Next</a></p>"#);
+        let two = xhtml("<p>Two</p>");
+        let mut book = book(&[("content.opf", &opf), ("one.xhtml", &one), ("two.xhtml", &two)]);
+        let mut revalidator = Revalidator::new();
+        assert_eq!(revalidator.refresh(&mut book).unwrap(), vec![]);
+
+        let edited = xhtml(r#"<p><img src="missing.png"/></p>"#);
+        book.storage.insert("OEBPS/two.xhtml", edited.into_bytes());
+        assert_eq!(
+            revalidator.refresh(&mut book).unwrap(),
+            vec![broken("OEBPS/two.xhtml", "missing.png")]
+        );
+        assert_eq!(
+            revalidator.refresh(&mut book).unwrap(),
+            vec![broken("OEBPS/two.xhtml", "missing.png")]
+        );
+
+        book.storage.remove("OEBPS/two.xhtml");
+        assert_eq!(
+            revalidator.refresh(&mut book).unwrap(),
+            vec![broken("OEBPS/one.xhtml", "two.xhtml")]
+        );
+
+        book.storage.insert("OEBPS/two.xhtml", two.into_bytes());
+        assert_eq!(revalidator.refresh(&mut book).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn failed_refreshes_are_retried() {
+        let opf = package(
+            r#"<item id="one" href="one.xhtml" media-type="application/xhtml+xml"/>
+               <item id="one" href="two.xhtml" media-type="application/xhtml+xml"/>"#,
+            &["one"],
+        );
+        let one = xhtml("<p>One</p>");
+        let mut book = book(&[("content.opf", &opf), ("one.xhtml", &one)]);
+        let mut revalidator = Revalidator::new();
+        let duplicate = Diagnostic::DuplicateItemId { id: "one".to_owned() };
+        assert_eq!(revalidator.refresh(&mut book).unwrap(), vec![duplicate.clone()]);
+
+        book.storage
+            .insert("OEBPS/content.opf", opf.as_bytes()[..opf.len() / 2].to_vec());
+        let diagnostics = revalidator.refresh(&mut book).unwrap();
+        assert!(matches!(
+            diagnostics.as_slice(),
+            [Diagnostic::MalformedDocument { file, .. }] if file == "OEBPS/content.opf"
+        ));
+
+        book.storage.remove("OEBPS/content.opf");
+        assert!(revalidator.refresh(&mut book).unwrap_err().is::<NotFound>());
+        assert!(revalidator.refresh(&mut book).unwrap_err().is::<NotFound>());
+
+        book.storage.insert("OEBPS/content.opf", opf.into_bytes());
+        assert_eq!(revalidator.refresh(&mut book).unwrap(), vec![duplicate.clone()]);
+
+        let edited = xhtml(r#"<p><a href="gone.xhtml">Gone</a></p>"#);
+        book.storage.insert("OEBPS/one.xhtml", edited.into_bytes());
+        book.timeouts.validate = Some(Duration::ZERO);
+        assert!(revalidator.refresh(&mut book).unwrap_err().is::<Timeout>());
+        assert_eq!(revalidator.diagnostics(), vec![duplicate.clone()]);
+
+        book.timeouts.validate = None;
+        assert_eq!(
+            revalidator.refresh(&mut book).unwrap(),
+            vec![duplicate, broken("OEBPS/one.xhtml", "gone.xhtml")]
+        );
+    }
+
+    #[test]
+    fn reports_a_malformed_ncx() {
+        let opf = package(
+            r#"<item id="ncx" href="toc.ncx" media-type="application/x-dtbncx+xml"/>
+               <item id="one" href="one.xhtml" media-type="application/xhtml+xml"/>"#,
+            &["one"],
+        );
+        let one = xhtml("<p>One</p>");
+        let mut book = book(&[("content.opf", &opf), ("toc.ncx", "<ncx><navMap>"), ("one.xhtml", &one)]);
+        let diagnostics = Revalidator::new().refresh(&mut book).unwrap();
+        assert!(matches!(
+            diagnostics.as_slice(),
+            [Diagnostic::MalformedDocument { file, .. }] if file == "OEBPS/toc.ncx"
+        ));
+    }
+}
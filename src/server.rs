@@ -0,0 +1,320 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+
+use crate::storage::{NotFound, Storage};
+use crate::timeout::Deadline;
+use crate::{links, path, Epub, Href};
+
+/// Serves the resources of a book mounted under a URL prefix.
+///
+/// The server does not depend on any HTTP framework, requests are passed in as a path
+/// relative to the prefix and the result is a plain [`Response`] to be translated by the caller.
+pub struct Server<S> {
+    book: Epub<S>,
+    prefix: String,
+    media_types: HashMap<String, String>,
+    spine: Vec<String>,
+}
+
+impl<S: Storage> Server<S> {
+    pub fn new(mut book: Epub<S>, prefix: &str) -> Result<Self> {
+        let resource = book.package()?;
+        let content = resource.content()?;
+
+        let media_types = content
+            .manifest
+            .items
+            .iter()
+            .filter_map(|item| Some((path::normalize(&item.href)?, item.media_type.clone().into_owned())))
+            .collect();
+        let spine = content
+            .spine_items()
+            .filter_map(|item| path::normalize(&item.href))
+            .collect();
+
+        let result = Self {
+            book,
+            prefix: prefix.trim_end_matches('/').to_owned(),
+            media_types,
+            spine,
+        };
+        Ok(result)
+    }
+
+    pub fn into_inner(self) -> Epub<S> {
+        self.book
+    }
+
+    /// Handles a request for a path relative to the mount prefix.
+    /// The range argument is the raw value of the `Range` header, if any.
+    /// Requests for multiple ranges are answered with the whole resource.
+    pub fn handle(&mut self, path: &str, range: Option<&str>) -> Response {
+        let path = path.split(['?', '#']).next().unwrap_or_default();
+        let path = match path::normalize(path) {
+            Some(path) => path,
+            None => return Response::status(400),
+        };
+        if path.is_empty() {
+            return match self.spine.first() {
+                Some(first) => Response::status(302).header("Location", format!("{}/{}", self.prefix, first)),
+                None => Response::status(404),
+            };
+        }
+
+        let bytes = match self.book.read_bytes(&Href::untyped(path.as_str()), Deadline::NONE) {
+            Ok(bytes) => bytes,
+            Err(err) if err.is::<NotFound>() => return Response::status(404),
+            Err(_) => return Response::status(500),
+        };
+        let media_type = self
+            .media_types
+            .get(&path)
+            .map(String::as_str)
+            .unwrap_or_else(|| guess_media_type(&path))
+            .to_owned();
+
+        let mut response = match media_type.as_str() {
+            "application/xhtml+xml" => match self.rewrite_links(&path, &bytes) {
+                Ok(body) => Response::ok(body),
+                Err(_) => Response::ok(bytes),
+            },
+            media if media.starts_with("audio/") || media.starts_with("video/") => match range
+                .filter(|range| !range.contains(','))
+                .map(|range| parse_range(range, bytes.len()))
+            {
+                Some(Some((start, end))) => Response::status(206)
+                    .header("Content-Range", format!("bytes {}-{}/{}", start, end - 1, bytes.len()))
+                    .body(bytes[start..end].to_vec()),
+                Some(None) => return Response::status(416).header("Content-Range", format!("bytes */{}", bytes.len())),
+                None => Response::ok(bytes),
+            }
+            .header("Accept-Ranges", "bytes".to_owned()),
+            _ => Response::ok(bytes),
+        };
+
+        if let Some(idx) = self.spine.iter().position(|href| *href == path) {
+            if let Some(prev) = idx.checked_sub(1).and_then(|idx| self.spine.get(idx)) {
+                response = response.header("Link", format!("<{}/{}>; rel=\"prev\"", self.prefix, prev));
+            }
+            if let Some(next) = self.spine.get(idx + 1) {
+                response = response.header("Link", format!("<{}/{}>; rel=\"next\"", self.prefix, next));
+            }
+        }
+        response.header("Content-Type", media_type)
+    }
+
+    /// Rewrites relative links in a document to absolute ones under the prefix,
+    /// so that the markup keeps working when embedded into a page served from elsewhere.
+    fn rewrite_links(&self, path: &str, bytes: &[u8]) -> Result<Vec<u8>> {
+        let text = std::str::from_utf8(bytes)?;
+        let doc = roxmltree::Document::parse(text)?;
+
+        let mut replacements = doc
+            .descendants()
+            .flat_map(|node| node.attributes())
+            .filter(|attr| links::LINK_ATTRIBUTES.contains(&attr.name()))
+            .filter(|attr| path::is_relative(attr.value()))
+            .filter_map(|attr| {
+                let resolved = path::resolve(path, attr.value())?;
+                let fragment = attr
+                    .value()
+                    .find('#')
+                    .map(|idx| &attr.value()[idx..])
+                    .unwrap_or_default();
+                Some((attr.value_range(), format!("{}/{}{}", self.prefix, resolved, fragment)))
+            })
+            .collect::<Vec<_>>();
+        replacements.sort_by_key(|(range, _)| range.start);
+
+        let mut result = String::with_capacity(text.len());
+        let mut last = 0;
+        for (range, url) in replacements {
+            result.push_str(&text[last..range.start]);
+            result.push_str(&url.replace('&', "&amp;").replace('"', "&quot;"));
+            last = range.end;
+        }
+        result.push_str(&text[last..]);
+        Ok(result.into_bytes())
+    }
+}
+
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(&'static str, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    fn status(status: u16) -> Self {
+        Self {
+            status,
+            headers: vec![],
+            body: vec![],
+        }
+    }
+
+    fn ok(body: Vec<u8>) -> Self {
+        Self::status(200).body(body)
+    }
+
+    fn header(mut self, name: &'static str, value: String) -> Self {
+        self.headers.push((name, value));
+        self
+    }
+
+    fn body(mut self, body: Vec<u8>) -> Self {
+        self.body = body;
+        self
+    }
+}
+
+/// Parses a single `bytes` range into a half-open interval, returns `None` if it's not satisfiable.
+fn parse_range(range: &str, len: usize) -> Option<(usize, usize)> {
+    let (start, end) = range.trim().strip_prefix("bytes=")?.split_once('-')?;
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", suffix) => {
+            let suffix: usize = suffix.parse().ok()?;
+            (len.checked_sub(suffix.min(len))?, len)
+        }
+        (start, "") => (start.parse().ok()?, len),
+        (start, end) => (
+            start.parse().ok()?,
+            end.parse::<usize>().ok()?.saturating_add(1).min(len),
+        ),
+    };
+    if start < end {
+        Some((start, end))
+    } else {
+        None
+    }
+}
+
+fn guess_media_type(path: &str) -> &'static str {
+    let extension = path.rsplit_once('.').map(|(_, ext)| ext).unwrap_or_default();
+    match extension.to_ascii_lowercase().as_str() {
+        "xhtml" | "xht" | "html" | "htm" => "application/xhtml+xml",
+        "css" => "text/css",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "webp" => "image/webp",
+        "ncx" => "application/x-dtbncx+xml",
+        "opf" => "application/oebps-package+xml",
+        "mp3" => "audio/mpeg",
+        "m4a" | "mp4a" => "audio/mp4",
+        "ogg" | "oga" => "audio/ogg",
+        "mp4" | "m4v" => "video/mp4",
+        "webm" => "video/webm",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "js" => "application/javascript",
+        "smil" => "application/smil+xml",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use crate::testing;
+
+    fn server() -> Server<MemoryStorage> {
+        let package = testing::package(
+            r#"<item id="one" href="text/one.xhtml" media-type="application/xhtml+xml"/>
+               <item id="two" href="text/two.xhtml" media-type="application/xhtml+xml"/>
+               <item id="three" href="text/three.xhtml" media-type="application/xhtml+xml"/>
+               <item id="track" href="./audio/track.bin" media-type="audio/mpeg"/>"#,
+            &["one", "two", "three"],
+        );
+        let one = testing::xhtml(r#"<a href="two.xhtml#start">Next</a><img src="../images/a.png"/>"#);
+        let two = testing::xhtml(r#"<a href="https://example.com/">Elsewhere</a>"#);
+        let book = testing::book(&[
+            ("content.opf", &package),
+            ("text/one.xhtml", &one),
+            ("text/two.xhtml", &two),
+            ("text/three.xhtml", "<html><body><p>Unclosed</body></html>"),
+            ("audio/track.bin", "0123456789"),
+        ]);
+        Server::new(book, "/books/1/").unwrap()
+    }
+
+    fn headers<'a>(response: &'a Response, name: &str) -> Vec<&'a str> {
+        response
+            .headers
+            .iter()
+            .filter(|(header, _)| *header == name)
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn redirects_to_the_first_chapter() {
+        let response = server().handle("", None);
+        assert_eq!(response.status, 302);
+        assert_eq!(headers(&response, "Location"), ["/books/1/text/one.xhtml"]);
+    }
+
+    #[test]
+    fn rewrites_links_and_links_neighbouring_chapters() {
+        let mut server = server();
+        let response = server.handle("text/one.xhtml", None);
+        assert_eq!(response.status, 200);
+        let body = String::from_utf8(response.body.clone()).unwrap();
+        assert!(body.contains(r#"<a href="/books/1/text/two.xhtml#start">"#));
+        assert!(body.contains(r#"<img src="/books/1/images/a.png"/>"#));
+        assert_eq!(headers(&response, "Link"), [r#"</books/1/text/two.xhtml>; rel="next""#]);
+        assert_eq!(headers(&response, "Content-Type"), ["application/xhtml+xml"]);
+
+        let response = server.handle("text/two.xhtml", None);
+        let body = String::from_utf8(response.body.clone()).unwrap();
+        assert!(body.contains(r#"<a href="https://example.com/">"#));
+        assert_eq!(
+            headers(&response, "Link"),
+            [
+                r#"</books/1/text/one.xhtml>; rel="prev""#,
+                r#"</books/1/text/three.xhtml>; rel="next""#
+            ]
+        );
+
+        let response = server.handle("text/three.xhtml", None);
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, b"<html><body><p>Unclosed</body></html>");
+        assert_eq!(headers(&response, "Link"), [r#"</books/1/text/two.xhtml>; rel="prev""#]);
+    }
+
+    #[test]
+    fn serves_byte_ranges() {
+        let mut server = server();
+        let response = server.handle("audio/track.bin", Some("bytes=2-4"));
+        assert_eq!(response.status, 206);
+        assert_eq!(response.body, b"234");
+        assert_eq!(headers(&response, "Content-Range"), ["bytes 2-4/10"]);
+        assert_eq!(headers(&response, "Content-Type"), ["audio/mpeg"]);
+
+        let response = server.handle("audio/track.bin", Some("bytes=-3"));
+        assert_eq!(response.status, 206);
+        assert_eq!(response.body, b"789");
+
+        let response = server.handle("audio/track.bin", Some("bytes=10-"));
+        assert_eq!(response.status, 416);
+        assert_eq!(headers(&response, "Content-Range"), ["bytes */10"]);
+
+        let response = server.handle("audio/track.bin", Some("bytes=0-1,4-5"));
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, b"0123456789");
+    }
+
+    #[test]
+    fn rejects_paths_outside_of_the_book() {
+        let mut server = server();
+        assert_eq!(server.handle("../META-INF/container.xml", None).status, 400);
+        assert_eq!(server.handle("text/../../mimetype", None).status, 400);
+        assert_eq!(server.handle("text/missing.xhtml", None).status, 404);
+    }
+}
@@ -0,0 +1,418 @@
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Cursor, Read, Seek};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use anyhow::Result;
+
+use crate::archive::{ArchiveMetadata, EntryMetadata, Timestamp};
+use crate::path;
+use crate::timeout::{Deadline, Guarded};
+
+/// Source of the files making up a book.
+///
+/// Implemented for zip archives, unpacked directories and in-memory maps, other backends like remote stores
+/// can be plugged in by implementing it for a custom type.
+pub trait Storage {
+    /// Lists all files in the storage.
+    fn entries(&mut self) -> Result<Vec<EntryInfo>>;
+
+    /// Opens a file for reading, fails with [`NotFound`] if there is no such file.
+    fn open(&mut self, name: &str) -> Result<Box<dyn Read + '_>>;
+
+    /// Lists names shared by more than one file, only one of which is accessible.
+    /// Only archives can contain such files, other storage has none.
+    fn duplicates(&self) -> Vec<String> {
+        vec![]
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryInfo {
+    /// Path of the file relative to the root of the container.
+    pub name: String,
+    pub size: u64,
+    /// Size of the file as stored, equal to `size` for uncompressed storage.
+    pub compressed_size: u64,
+    /// Time of the last modification, if the storage keeps track of it.
+    /// Zip archives don't record the time zone, their timestamps are taken to be in UTC.
+    pub modified: Option<SystemTime>,
+}
+
+/// Error returned by storage when the requested file doesn't exist.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NotFound {
+    pub name: String,
+}
+
+impl fmt::Display for NotFound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "file '{}' not found", self.name)
+    }
+}
+
+impl std::error::Error for NotFound {}
+
+/// Decides which of the files sharing a name in a malformed archive is used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicatePolicy {
+    FirstWins,
+    /// The default, matching most zip tools which let later entries overwrite earlier ones.
+    #[default]
+    LastWins,
+}
+
+#[derive(Debug)]
+pub struct ZipStorage<R> {
+    archive: zip::ZipArchive<Guarded<R>>,
+    /// Index of the entry used for each name, according to the duplicate policy.
+    names: HashMap<String, usize>,
+    duplicates: Vec<String>,
+}
+
+impl<R: Read + Seek> ZipStorage<R> {
+    pub fn new(input: R) -> Result<Self> {
+        Self::with_policy(input, DuplicatePolicy::default())
+    }
+
+    pub fn with_policy(input: R, policy: DuplicatePolicy) -> Result<Self> {
+        Self::with_deadline(input, Deadline::NONE, policy)
+    }
+
+    pub(crate) fn with_deadline(input: R, deadline: Deadline, policy: DuplicatePolicy) -> Result<Self> {
+        let shared = Arc::new(Mutex::new(deadline));
+        let with_timeout = |err: zip::result::ZipError| match deadline.check() {
+            Err(timeout) => anyhow::Error::new(timeout),
+            Ok(()) => err.into(),
+        };
+        let mut archive = zip::ZipArchive::new(Guarded::new(input, shared.clone())).map_err(with_timeout)?;
+
+        let mut names = HashMap::new();
+        let mut duplicates = vec![];
+        for idx in 0..archive.len() {
+            let name = archive.by_index_raw(idx).map_err(with_timeout)?.name().to_owned();
+            match names.entry(name) {
+                Entry::Vacant(entry) => {
+                    entry.insert(idx);
+                }
+                Entry::Occupied(mut entry) => {
+                    if !duplicates.contains(entry.key()) {
+                        duplicates.push(entry.key().clone());
+                    }
+                    if policy == DuplicatePolicy::LastWins {
+                        entry.insert(idx);
+                    }
+                }
+            }
+        }
+        *shared.lock().unwrap() = Deadline::NONE;
+        Ok(Self {
+            archive,
+            names,
+            duplicates,
+        })
+    }
+
+    /// Returns the comment of the archive and the timestamps and comments of its files.
+    pub fn metadata(&mut self) -> Result<ArchiveMetadata> {
+        let mut entries = BTreeMap::new();
+        for idx in self.names.values().copied() {
+            let file = self.archive.by_index_raw(idx)?;
+            if file.is_dir() {
+                continue;
+            }
+            let metadata = EntryMetadata {
+                modified: file.last_modified().into(),
+                comment: file.comment().to_owned(),
+            };
+            entries.insert(file.name().to_owned(), metadata);
+        }
+        Ok(ArchiveMetadata {
+            comment: self.archive.comment().to_vec(),
+            entries,
+        })
+    }
+}
+
+impl<R: Read + Seek> Storage for ZipStorage<R> {
+    fn entries(&mut self) -> Result<Vec<EntryInfo>> {
+        let mut indices: Vec<usize> = self.names.values().copied().collect();
+        indices.sort_unstable();
+        let mut entries = vec![];
+        for idx in indices {
+            let file = self.archive.by_index_raw(idx)?;
+            if file.is_dir() {
+                continue;
+            }
+            entries.push(EntryInfo {
+                name: file.name().to_owned(),
+                size: file.size(),
+                compressed_size: file.compressed_size(),
+                modified: Timestamp::from(file.last_modified()).to_system_time(),
+            });
+        }
+        Ok(entries)
+    }
+
+    fn open(&mut self, name: &str) -> Result<Box<dyn Read + '_>> {
+        match self.names.get(name) {
+            Some(idx) => Ok(Box::new(self.archive.by_index(*idx)?)),
+            None => Err(NotFound { name: name.to_owned() }.into()),
+        }
+    }
+
+    fn duplicates(&self) -> Vec<String> {
+        self.duplicates.clone()
+    }
+}
+
+/// Storage keeping all files in memory, handy for generated books and tests.
+#[derive(Debug, Default, Clone)]
+pub struct MemoryStorage {
+    files: BTreeMap<String, Vec<u8>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, name: impl Into<String>, bytes: Vec<u8>) {
+        self.files.insert(name.into(), bytes);
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Vec<u8>> {
+        self.files.remove(name)
+    }
+}
+
+impl From<BTreeMap<String, Vec<u8>>> for MemoryStorage {
+    fn from(files: BTreeMap<String, Vec<u8>>) -> Self {
+        Self { files }
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn entries(&mut self) -> Result<Vec<EntryInfo>> {
+        let entries = self
+            .files
+            .iter()
+            .map(|(name, bytes)| EntryInfo {
+                name: name.clone(),
+                size: bytes.len() as u64,
+                compressed_size: bytes.len() as u64,
+                modified: None,
+            })
+            .collect();
+        Ok(entries)
+    }
+
+    fn open(&mut self, name: &str) -> Result<Box<dyn Read + '_>> {
+        match self.files.get(name) {
+            Some(bytes) => Ok(Box::new(Cursor::new(bytes.as_slice()))),
+            None => Err(NotFound { name: name.to_owned() }.into()),
+        }
+    }
+}
+
+/// Storage backed by a directory laid out like an unpacked book.
+#[derive(Debug, Clone)]
+pub struct DirStorage {
+    root: PathBuf,
+}
+
+impl DirStorage {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Maps a container path onto the file system, refusing paths which would escape the root.
+    pub fn file_path(&self, name: &str) -> Option<PathBuf> {
+        let normalized = path::normalize(name)?;
+        Some(
+            normalized
+                .split('/')
+                .fold(self.root.clone(), |path, segment| path.join(segment)),
+        )
+    }
+}
+
+impl Storage for DirStorage {
+    fn entries(&mut self) -> Result<Vec<EntryInfo>> {
+        let mut entries = vec![];
+        let mut pending = vec![(self.root.clone(), String::new())];
+        while let Some((dir, prefix)) = pending.pop() {
+            for entry in fs::read_dir(&dir)? {
+                let entry = entry?;
+                let name = prefix.clone() + &entry.file_name().to_string_lossy();
+                let metadata = entry.metadata()?;
+                if metadata.is_dir() {
+                    pending.push((entry.path(), name + "/"));
+                } else {
+                    entries.push(EntryInfo {
+                        name,
+                        size: metadata.len(),
+                        compressed_size: metadata.len(),
+                        modified: metadata.modified().ok(),
+                    });
+                }
+            }
+        }
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(entries)
+    }
+
+    fn open(&mut self, name: &str) -> Result<Box<dyn Read + '_>> {
+        let not_found = || NotFound { name: name.to_owned() };
+        let path = self.file_path(name).ok_or_else(not_found)?;
+        match File::open(path) {
+            Ok(file) => Ok(Box::new(file)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Err(not_found().into()),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+impl<S: Storage + ?Sized> Storage for Box<S> {
+    fn entries(&mut self) -> Result<Vec<EntryInfo>> {
+        (**self).entries()
+    }
+
+    fn open(&mut self, name: &str) -> Result<Box<dyn Read + '_>> {
+        (**self).open(name)
+    }
+
+    fn duplicates(&self) -> Vec<String> {
+        (**self).duplicates()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::time::Duration;
+
+    use zip::write::FileOptions;
+
+    use super::*;
+    use crate::diagnostics::Diagnostic;
+    use crate::extract::container_xml;
+    use crate::testing;
+    use crate::timeout::Timeouts;
+    use crate::Epub;
+
+    #[test]
+    fn zip_entries_report_modification_time() {
+        let mut zip = zip::ZipWriter::new(Cursor::new(vec![]));
+        let modified = zip::DateTime::from_date_and_time(2020, 2, 29, 12, 34, 56).unwrap();
+        zip.start_file("OEBPS/content.opf", FileOptions::default().last_modified_time(modified))
+            .unwrap();
+        zip.write_all(b"<package/>").unwrap();
+        zip.add_directory("OEBPS/text/", FileOptions::default()).unwrap();
+        let bytes = zip.finish().unwrap().into_inner();
+
+        let mut storage = ZipStorage::new(Cursor::new(bytes)).unwrap();
+        let entries = storage.entries().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].name, "OEBPS/content.opf");
+        assert_eq!(entries[0].size, 10);
+        assert_eq!(
+            entries[0].modified,
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1582979696))
+        );
+    }
+
+    #[test]
+    fn duplicate_entries_follow_the_policy() {
+        let package = testing::package(
+            r#"<item id="one" href="one.xhtml" media-type="application/xhtml+xml"/>"#,
+            &["one"],
+        );
+        let first = testing::xhtml("<p>First</p>");
+        let second = testing::xhtml("<p>Second</p>");
+        let mut zip = zip::ZipWriter::new(Cursor::new(vec![]));
+        for (name, contents) in [
+            ("mimetype", "application/epub+zip"),
+            ("META-INF/container.xml", &container_xml()),
+            ("OEBPS/content.opf", &package),
+            ("OEBPS/one.xhtml", &first),
+            ("OEBPS/one.xhtml", &second),
+        ] {
+            zip.start_file(name, FileOptions::default()).unwrap();
+            zip.write_all(contents.as_bytes()).unwrap();
+        }
+        let bytes = zip.finish().unwrap().into_inner();
+
+        for (policy, expected) in [
+            (DuplicatePolicy::FirstWins, &first),
+            (DuplicatePolicy::LastWins, &second),
+        ] {
+            let mut book = Epub::open_with(Cursor::new(bytes.clone()), Timeouts::default(), policy).unwrap();
+            assert_eq!(book.storage.duplicates(), ["OEBPS/one.xhtml"]);
+            assert_eq!(book.storage.entries().unwrap().len(), 4);
+
+            let mut contents = String::new();
+            book.storage
+                .open("OEBPS/one.xhtml")
+                .unwrap()
+                .read_to_string(&mut contents)
+                .unwrap();
+            assert_eq!(&contents, expected);
+
+            assert_eq!(
+                book.validate().unwrap(),
+                [Diagnostic::DuplicateEntry {
+                    name: "OEBPS/one.xhtml".to_owned()
+                }]
+            );
+        }
+    }
+
+    #[test]
+    fn directories_are_opened_as_books() {
+        let base = std::env::temp_dir().join(format!("epubs-dir-storage-{}", std::process::id()));
+        let root = base.join("book");
+        let _ = fs::remove_dir_all(&base);
+        let package = testing::package(
+            r#"<item id="one" href="text/one.xhtml" media-type="application/xhtml+xml"/>"#,
+            &["one"],
+        );
+        let files = [
+            ("META-INF/container.xml", container_xml()),
+            ("OEBPS/content.opf", package),
+            ("OEBPS/text/one.xhtml", testing::xhtml("<p>One</p>")),
+        ];
+        for (name, contents) in &files {
+            let path = root.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        fs::write(base.join("secret.txt"), "secret").unwrap();
+
+        let mut book = Epub::open_dir(&root).unwrap();
+        let entries = book.storage.entries().unwrap();
+        let names: Vec<&str> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(
+            names,
+            ["META-INF/container.xml", "OEBPS/content.opf", "OEBPS/text/one.xhtml"]
+        );
+        assert_eq!(entries[2].size, files[2].1.len() as u64);
+        assert!(entries[2].modified.is_some());
+        let resource = book.package().unwrap();
+        assert_eq!(resource.content().unwrap().metadata.title, "Test");
+
+        for name in ["../secret.txt", "OEBPS/../../secret.txt", "OEBPS/missing.xhtml"] {
+            assert!(book.storage.open(name).err().unwrap().is::<NotFound>());
+        }
+        assert!(Epub::open_dir(&base).is_err());
+        fs::remove_dir_all(&base).unwrap();
+    }
+}
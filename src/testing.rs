@@ -0,0 +1,36 @@
+//! Helpers for building small books in memory for tests.
+
+use crate::extract::container_xml;
+use crate::storage::MemoryStorage;
+use crate::timeout::Timeouts;
+use crate::Epub;
+
+/// Builds a book from files given by their path within the package directory,
+/// together with a container pointing at `OEBPS/content.opf`.
+pub(crate) fn book(files: &[(&str, &str)]) -> Epub<MemoryStorage> {
+    let mut storage = MemoryStorage::new();
+    storage.insert("META-INF/container.xml", container_xml().into_bytes());
+    for (name, contents) in files {
+        storage.insert(format!("OEBPS/{}", name), contents.as_bytes().to_vec());
+    }
+    Epub::from_storage(storage, Timeouts::default()).unwrap()
+}
+
+/// Builds a package document with the given manifest items and a spine referencing the ids in `spine`.
+pub(crate) fn package(items: &str, spine: &[&str]) -> String {
+    let refs: String = spine.iter().map(|id| format!(r#"<itemref idref="{}"/>"#, id)).collect();
+    format!(
+        r#"<package xmlns="http://www.idpf.org/2007/opf" version="2.0" unique-identifier="id">
+<metadata xmlns:dc="http://purl.org/dc/elements/1.1/"><dc:title>Test</dc:title><dc:language>en</dc:language><dc:identifier>test</dc:identifier></metadata>
+<manifest>{}</manifest><spine toc="ncx">{}</spine><guide/></package>"#,
+        items, refs
+    )
+}
+
+/// Wraps body markup into an XHTML document.
+pub(crate) fn xhtml(body: &str) -> String {
+    format!(
+        r#"<html xmlns="http://www.w3.org/1999/xhtml"><head><title>Test</title></head><body>{}</body></html>"#,
+        body
+    )
+}
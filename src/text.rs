@@ -0,0 +1,38 @@
+/// Namespace of the `xml:` prefix, used by attributes like `xml:lang`.
+pub(crate) const XML_NAMESPACE: &str = "http://www.w3.org/XML/1998/namespace";
+
+/// Returns the body element of a document, or the root element of documents without one.
+pub(crate) fn body<'a, 'input>(doc: &'a roxmltree::Document<'input>) -> roxmltree::Node<'a, 'input> {
+    let root = doc.root_element();
+    root.children()
+        .find(|node| node.tag_name().name() == "body")
+        .unwrap_or(root)
+}
+
+/// Returns the text nodes below a node in document order, leaving out the contents
+/// of `style` and `script` elements.
+pub(crate) fn text_nodes<'a, 'input: 'a>(
+    node: roxmltree::Node<'a, 'input>,
+) -> impl Iterator<Item = roxmltree::Node<'a, 'input>> {
+    node.descendants().filter(|node| node.is_text()).filter(|node| {
+        !node
+            .ancestors()
+            .any(|node| matches!(node.tag_name().name(), "style" | "script"))
+    })
+}
+
+/// Concatenates all text below a node, collapsing runs of whitespace into single spaces.
+pub(crate) fn node_text(node: roxmltree::Node) -> String {
+    let text: String = text_nodes(node).filter_map(|node| node.text()).collect();
+    collapse_whitespace(&text)
+}
+
+/// Returns the text of the body of a document with runs of whitespace collapsed,
+/// the text chapter hashes, anchors and fingerprints are based on.
+pub(crate) fn body_text(doc: &roxmltree::Document) -> String {
+    node_text(body(doc))
+}
+
+pub(crate) fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
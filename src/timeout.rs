@@ -0,0 +1,151 @@
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Time budgets for operations on untrusted books, unlimited by default.
+///
+/// Budgets are checked between steps, like reading a chunk of an entry or parsing a document,
+/// a step which has started runs to completion. A single large document can therefore overrun
+/// the parse and validate budgets by the time it takes to parse it, and parsing a lone resource
+/// with [`crate::Resource::content`], [`crate::Resource::toc`] or [`crate::Resource::doc`] has no budget
+/// at all. Callers handling untrusted books should also cap the size of entries they parse,
+/// as listed by [`crate::storage::Storage::entries`].
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Timeouts {
+    /// Budget for reading the zip directory when opening a book.
+    pub open: Option<Duration>,
+    /// Budget for reading and decompressing a single entry.
+    pub read: Option<Duration>,
+    /// Budget for operations which parse many documents at once, like [`crate::Epub::synthesize_toc`].
+    pub parse: Option<Duration>,
+    /// Budget for validating a whole book with [`crate::Epub::validate`] or
+    /// [`crate::revalidate::Revalidator::refresh`], covering all the files read and parsed on the way.
+    pub validate: Option<Duration>,
+}
+
+/// Error returned when an operation runs out of its time budget.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeout {
+    pub operation: Operation,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Open,
+    Read,
+    Parse,
+    Validate,
+}
+
+impl fmt::Display for Timeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let operation = match self.operation {
+            Operation::Open => "open",
+            Operation::Read => "read",
+            Operation::Parse => "parse",
+            Operation::Validate => "validate",
+        };
+        write!(f, "{} operation timed out", operation)
+    }
+}
+
+impl std::error::Error for Timeout {}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Deadline {
+    at: Option<Instant>,
+    operation: Operation,
+}
+
+impl Deadline {
+    pub(crate) const NONE: Self = Self {
+        at: None,
+        operation: Operation::Read,
+    };
+
+    pub(crate) fn after(budget: Option<Duration>, operation: Operation) -> Self {
+        Self {
+            at: budget.map(|budget| Instant::now() + budget),
+            operation,
+        }
+    }
+
+    /// Returns whichever of the deadlines passes first.
+    pub(crate) fn earliest(self, other: Self) -> Self {
+        match (self.at, other.at) {
+            (Some(at), Some(other_at)) if other_at < at => other,
+            (Some(_), _) | (None, None) => self,
+            (None, Some(_)) => other,
+        }
+    }
+
+    pub(crate) fn check(&self) -> Result<(), Timeout> {
+        match self.at {
+            Some(at) if Instant::now() >= at => Err(Timeout {
+                operation: self.operation,
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Reader which fails once the deadline it shares with its owner has passed.
+#[derive(Debug)]
+pub(crate) struct Guarded<R> {
+    inner: R,
+    deadline: Arc<Mutex<Deadline>>,
+}
+
+impl<R> Guarded<R> {
+    pub(crate) fn new(inner: R, deadline: Arc<Mutex<Deadline>>) -> Self {
+        Self { inner, deadline }
+    }
+
+    fn check(&self) -> io::Result<()> {
+        let deadline = *self.deadline.lock().unwrap();
+        deadline
+            .check()
+            .map_err(|err| io::Error::new(io::ErrorKind::TimedOut, err))
+    }
+}
+
+impl<R: Read> Read for Guarded<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.check()?;
+        self.inner.read(buf)
+    }
+}
+
+impl<R: Seek> Seek for Guarded<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.check()?;
+        self.inner.seek(pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn earliest_deadline_wins() {
+        let soon = Deadline::after(Some(Duration::ZERO), Operation::Validate);
+        let later = Deadline::after(Some(Duration::from_secs(3600)), Operation::Read);
+        for (a, b) in [
+            (soon, later),
+            (later, soon),
+            (soon, Deadline::NONE),
+            (Deadline::NONE, soon),
+        ] {
+            assert_eq!(
+                a.earliest(b).check(),
+                Err(Timeout {
+                    operation: Operation::Validate
+                })
+            );
+        }
+        assert_eq!(later.earliest(Deadline::NONE).check(), Ok(()));
+        assert_eq!(Deadline::NONE.earliest(Deadline::NONE).check(), Ok(()));
+    }
+}